@@ -0,0 +1,168 @@
+use super::*;
+
+/// Layer of the world, by depth.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Biome {
+    Topsoil,
+    Clay,
+    Granite,
+    /// Has open caverns
+    Crystal,
+    Magma,
+}
+
+/// Open caverns, in different cells than the fluid pockets and loose patches
+const CAVERNS: Scatter = Scatter {
+    salt: 0xCA7E_5EED,
+    cell_size: 14,
+    chance: 40,
+    radius: 2.5..4.5,
+    // Wider than tall
+    stretch: 1.5,
+};
+
+impl Biome {
+    pub const ALL: [Self; 5] = [
+        Self::Topsoil,
+        Self::Clay,
+        Self::Granite,
+        Self::Crystal,
+        Self::Magma,
+    ];
+
+    /// Depth at which the layer starts
+    pub fn min_depth(self) -> i32 {
+        match self {
+            Self::Topsoil => 1,
+            Self::Clay => 11,
+            Self::Granite => 36,
+            Self::Crystal => 81,
+            Self::Magma => 131,
+        }
+    }
+    /// Layer at the depth, topsoil above the ground
+    pub fn at_depth(depth: i32) -> Self {
+        Self::ALL
+            .iter()
+            .rev()
+            .copied()
+            .find(|biome| depth >= biome.min_depth())
+            .unwrap_or(Self::Topsoil)
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Topsoil => "Topsoil",
+            Self::Clay => "Clay",
+            Self::Granite => "Granite",
+            Self::Crystal => "Crystal caverns",
+            Self::Magma => "Magma layer",
+        }
+    }
+    /// Tile the layer is made of
+    pub fn tile(self) -> Tile {
+        match self {
+            Self::Topsoil => Tile::Dirt,
+            Self::Clay => Tile::Clay,
+            Self::Granite => Tile::Granite,
+            Self::Crystal => Tile::Crystal,
+            Self::Magma => Tile::Basalt,
+        }
+    }
+    /// Tint of the cave walls behind dug out tiles
+    pub fn background_color(self) -> Color<f32> {
+        match self {
+            Self::Topsoil => Color::rgb(0.5, 0.5, 0.5),
+            Self::Clay => Color::rgb(0.5, 0.45, 0.45),
+            Self::Granite => Color::rgb(0.5, 0.5, 0.5),
+            Self::Crystal => Color::rgb(0.45, 0.5, 0.6),
+            Self::Magma => Color::rgb(0.7, 0.5, 0.45),
+        }
+    }
+    /// Weights of the items dropped by breaking the layer's tiles
+    pub fn loot(self) -> &'static [(ItemType, u32)] {
+        match self {
+            Self::Topsoil => &[(ItemType::Block, 95), (ItemType::Chest, 5)],
+            Self::Clay => &[
+                (ItemType::Block, 88),
+                (ItemType::Chest, 10),
+                (ItemType::Ladder, 2),
+            ],
+            Self::Granite => &[
+                (ItemType::Block, 85),
+                (ItemType::Chest, 12),
+                (ItemType::Torch, 3),
+            ],
+            Self::Crystal => &[
+                (ItemType::Block, 75),
+                (ItemType::Chest, 20),
+                (ItemType::Torch, 5),
+            ],
+            Self::Magma => &[(ItemType::Block, 75), (ItemType::Chest, 25)],
+        }
+    }
+    /// Chests are worth the depth times this
+    pub fn treasure_multiplier(self) -> usize {
+        match self {
+            Self::Topsoil | Self::Clay | Self::Granite => 1,
+            Self::Crystal => 2,
+            Self::Magma => 3,
+        }
+    }
+    /// Picks an item from the loot table, with the value it is worth
+    pub fn random_loot(self, depth: i32, rng: &mut impl rand::Rng) -> (ItemType, usize) {
+        let loot = self.loot();
+        let total: u32 = loot.iter().map(|&(_, weight)| weight).sum();
+        let mut roll = rng.gen_range(0..total);
+        for &(item_type, weight) in loot {
+            if roll < weight {
+                let value = match item_type {
+                    ItemType::Chest => depth.max(1) as usize * self.treasure_multiplier(),
+                    _ => 0,
+                };
+                return (item_type, value);
+            }
+            roll -= weight;
+        }
+        unreachable!()
+    }
+}
+
+/// Whether the position is inside one of the open caverns of the crystal layer
+pub(crate) fn generate_cavern(seed: u64, position: Vec2<i32>) -> bool {
+    CAVERNS
+        .find(seed, position, |center| {
+            Biome::at_depth(-center.y) == Biome::Crystal
+        })
+        .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_start_at_their_depth() {
+        assert_eq!(Biome::at_depth(-5), Biome::Topsoil);
+        assert_eq!(Biome::at_depth(10), Biome::Topsoil);
+        assert_eq!(Biome::at_depth(11), Biome::Clay);
+        assert_eq!(Biome::at_depth(35), Biome::Clay);
+        assert_eq!(Biome::at_depth(36), Biome::Granite);
+        assert_eq!(Biome::at_depth(81), Biome::Crystal);
+        assert_eq!(Biome::at_depth(130), Biome::Crystal);
+        assert_eq!(Biome::at_depth(131), Biome::Magma);
+        assert_eq!(Biome::at_depth(1000), Biome::Magma);
+    }
+
+    #[test]
+    fn generated_rows_are_made_of_their_layer() {
+        let model = Model::new(0);
+        for &biome in &Biome::ALL {
+            let y = -(biome.min_depth() + 2);
+            let row = (-WORLD_SIZE..=WORLD_SIZE).filter_map(|x| model.tiles.get(&vec2(x, y)));
+            let (matching, total) = row.fold((0, 0), |(matching, total), &tile| {
+                (matching + (tile == biome.tile()) as usize, total + 1)
+            });
+            assert!(matching * 2 > total, "{:?} at depth {}", biome, -y);
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy)]
+pub enum BotBehaviour {
+    Wander,
+    DigDown,
+    PlaceLadders,
+    SellChests,
+}
+
+impl BotBehaviour {
+    pub const ALL: [Self; 4] = [
+        Self::Wander,
+        Self::DigDown,
+        Self::PlaceLadders,
+        Self::SellChests,
+    ];
+}
+
+impl std::str::FromStr for BotBehaviour {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "wander" => Self::Wander,
+            "dig" => Self::DigDown,
+            "ladders" => Self::PlaceLadders,
+            "sell" => Self::SellChests,
+            _ => return Err(format!("Unknown bot behaviour {:?}", s)),
+        })
+    }
+}
+
+/// Headless client that plays by a script, used to put load on the server.
+pub struct Bot {
+    behaviour: BotBehaviour,
+    player: Player,
+    model: Model,
+    connection: geng::net::client::Connection<ServerMessage, ClientMessage>,
+    home_x: f32,
+    direction: f32,
+    direction_timer: f32,
+    picking_up: bool,
+    /// Tile being dug and the swings it took so far
+    dig_progress: Option<(Vec2<i32>, usize)>,
+}
+
+impl Bot {
+    /// Joins the server, returns why not if it didn't welcome the bot
+    pub async fn connect(addr: &str, behaviour: BotBehaviour) -> Result<Self, String> {
+        let mut connection = geng::net::client::connect(addr).await;
+        connection.send(ClientMessage::Join(None, Identity::random()));
+        let (message, connection) = connection.into_future().await;
+        let welcome = match message {
+            Some(ServerMessage::Welcome(message)) => message,
+            Some(ServerMessage::Kicked(reason)) => return Err(format!("Kicked: {}", reason)),
+            Some(message) => return Err(format!("Expected a welcome, got {:?}", message)),
+            None => return Err("Disconnected from the server".to_owned()),
+        };
+        let player = welcome
+            .player_id
+            .and_then(|player_id| welcome.model.players.get(&player_id));
+        let mut player = match player {
+            Some(player) => player.clone(),
+            None => return Err("Welcomed without a player".to_owned()),
+        };
+        player.name = format!("bot{}", player.id.raw());
+        Ok(Self {
+            behaviour,
+            player,
+            model: welcome.model,
+            connection,
+            home_x: match behaviour {
+                BotBehaviour::SellChests => global_rng().gen_range(8.0..40.0),
+                _ => global_rng().gen_range(-40.0..40.0),
+            },
+            direction: 0.0,
+            direction_timer: 0.0,
+            picking_up: false,
+            dig_progress: None,
+        })
+    }
+
+    /// Returns the number of updates and events received from the server,
+    /// or why the bot can't go on when it was kicked or disconnected.
+    pub fn update(&mut self, delta_time: f32) -> Result<(usize, usize), String> {
+        let mut messages = Vec::new();
+        // Not new_messages, which panics once the server closes the connection
+        loop {
+            match self.connection.next().now_or_never() {
+                Some(Some(message)) => messages.push(message),
+                Some(None) => return Err("Disconnected from the server".to_owned()),
+                None => break,
+            }
+        }
+        let received = !messages.is_empty();
+        let mut updates = 0;
+        let mut events = 0;
+        for message in messages {
+            match message {
+                ServerMessage::Update(new_events) => {
+                    updates += 1;
+                    events += new_events.len();
+                    for event in new_events {
+                        match event {
+                            Event::PlayerHurt(player_id, damage) if player_id == self.player.id => {
+                                self.player.damage(damage);
+                            }
+                            Event::ItemStolen(player_id, _) if player_id == self.player.id => {
+                                self.player.item = None;
+                            }
+                            _ => {}
+                        }
+                        self.model.handle(event);
+                    }
+                }
+                ServerMessage::PickedUp(item) => {
+                    self.picking_up = false;
+                    self.player.item = Some(item);
+                }
+                ServerMessage::PickUpFailed(_) => {
+                    self.picking_up = false;
+                }
+                ServerMessage::Kicked(reason) => return Err(format!("Kicked: {}", reason)),
+                ServerMessage::Confirmed(_) | ServerMessage::Rejected(_) => {}
+                // A new round started in a new world
+                ServerMessage::Welcome(welcome) => {
+                    self.model = welcome.model;
+                    self.player = self.model.players[&self.player.id].clone();
+                    self.picking_up = false;
+                    self.connection.send(ClientMessage::Welcomed);
+                }
+            }
+        }
+        // After the messages, so a new round's welcome resets the player first
+        if received {
+            self.connection
+                .send(ClientMessage::Event(Event::PlayerUpdated(
+                    self.player.clone(),
+                    self.model.time,
+                )));
+        }
+        self.think(delta_time);
+        self.player.update(&self.model.tiles, delta_time);
+        if self.player.is_dead() {
+            self.player.item = None;
+            self.player.respawn();
+            self.connection.send(ClientMessage::Respawn);
+        }
+        Ok((updates, events))
+    }
+
+    fn think(&mut self, delta_time: f32) {
+        self.player.target_velocity = vec2(0.0, 0.0);
+        match self.behaviour {
+            BotBehaviour::Wander => {
+                self.direction_timer -= delta_time;
+                if self.direction_timer <= 0.0 {
+                    self.direction_timer = global_rng().gen_range(0.5..3.0);
+                    self.direction = global_rng().gen_range(-1..=1) as f32;
+                }
+                self.player.target_velocity.x = self.direction;
+                if global_rng().gen_bool(0.05) {
+                    self.player.target_velocity.y = 1.0;
+                }
+            }
+            BotBehaviour::DigDown => {
+                if self.go_home() {
+                    let below = self.tile_position() - vec2(0, 1);
+                    self.dig(&[below]);
+                }
+            }
+            BotBehaviour::PlaceLadders => {
+                if self.go_home() {
+                    self.pick_up(|item| item.item_type.placed().is_some());
+                    let position = self.tile_position();
+                    let placed = self
+                        .player
+                        .item
+                        .as_ref()
+                        .and_then(|item| item.item_type.placed());
+                    if let Some(tile) = placed {
+                        let above = position + vec2(0, 1);
+                        if above.y < 0 && !self.model.tiles.contains_key(&above) {
+                            self.send(Event::TilePlaced(above, tile));
+                            self.player.item = None;
+                        }
+                    }
+                    self.dig(&[position - vec2(0, 1)]);
+                }
+            }
+            BotBehaviour::SellChests => {
+                if self.player.item.is_some() {
+                    self.sell_chest();
+                } else if self.go_home() {
+                    self.pick_up(|item| item.item_type == ItemType::Chest);
+                    // Dig a staircase, so it is possible to walk back up with the loot
+                    let position = self.tile_position();
+                    let targets = [position + vec2(1, 0), position + vec2(1, -1)];
+                    if !self.dig(&targets) {
+                        self.player.target_velocity.x = 1.0;
+                        self.home_x = self.player.position.x;
+                    }
+                }
+            }
+        }
+    }
+
+    fn tile_position(&self) -> Vec2<i32> {
+        (self.player.position + self.player.size / 2.0).map(|x| x.floor() as i32)
+    }
+
+    /// Walks to the starting point, returns whether it is already there.
+    fn go_home(&mut self) -> bool {
+        let delta = self.home_x - self.player.position.x;
+        if delta.abs() < 0.1 {
+            return true;
+        }
+        self.player.target_velocity.x = delta.signum();
+        if global_rng().gen_bool(0.05) {
+            self.player.target_velocity.y = 1.0;
+        }
+        false
+    }
+
+    /// Swings at the first existing tile of the targets, returns whether there was one.
+    fn dig(&mut self, targets: &[Vec2<i32>]) -> bool {
+        let (target, tile) = match targets.iter().find_map(|&position| {
+            self.model
+                .tiles
+                .get(&position)
+                .filter(|tile| !tile.is_fluid())
+                .map(|&tile| (position, tile))
+        }) {
+            Some(target) => target,
+            None => {
+                self.player.swing = None;
+                return false;
+            }
+        };
+        match self.player.swing {
+            None => self.player.swing = Some(0.0),
+            Some(swing) if swing > 1.0 => {
+                let hits = match self.dig_progress {
+                    Some((position, hits)) if position == target => hits + 1,
+                    _ => 1,
+                };
+                if hits >= tile.hardness() {
+                    self.dig_progress = None;
+                    self.send(Event::TileBroken(target));
+                    self.model.tiles.remove(&target);
+                } else {
+                    self.dig_progress = Some((target, hits));
+                }
+                self.player.swing = Some(0.0);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn pick_up(&mut self, filter: impl Fn(&Item) -> bool) {
+        if self.picking_up || self.player.item.is_some() {
+            return;
+        }
+        let position = self.player.position;
+        let item = self
+            .model
+            .items
+            .values()
+            .filter(|item| filter(item))
+            .find(|item| (item.position - position).len() < Player::RANGE);
+        if let Some(item) = item {
+            let item_id = item.id;
+            self.picking_up = true;
+            self.connection.send(ClientMessage::PickUp(item_id));
+        }
+    }
+
+    fn sell_chest(&mut self) {
+        let shop = self.model.shops.iter().find(|shop| {
+            matches!(
+                shop.shop_type,
+                ShopType::Sell {
+                    require_item: ItemType::Chest,
+                    ..
+                }
+            )
+        });
+        let shop_position = match shop {
+            Some(shop) => shop.position,
+            None => return,
+        };
+        if AABB::point(vec2(shop_position, 0.0) - self.player.size)
+            .extend_positive(vec2(2.0, 2.0))
+            .contains(self.player.position)
+        {
+            if let Some(item) = self.player.item.take() {
+                self.player.money = self.player.money.saturating_add(item.value);
+                self.connection.send(ClientMessage::Sell);
+            }
+        } else {
+            self.player.target_velocity =
+                vec2((shop_position + 0.5 - self.player.position.x).signum(), 1.0);
+        }
+    }
+
+    fn send(&mut self, event: Event) {
+        self.connection.send(ClientMessage::Event(event));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct BotStats {
+    connected: std::sync::atomic::AtomicUsize,
+    updates: std::sync::atomic::AtomicUsize,
+    events: std::sync::atomic::AtomicUsize,
+}
+
+/// Spawns bots on separate threads and logs the throughput every second.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run(addr: &str, count: usize, behaviour: Option<BotBehaviour>) {
+    use std::sync::atomic::Ordering;
+    let stats = Arc::new(BotStats::default());
+    for index in 0..count {
+        let addr = addr.to_owned();
+        let stats = stats.clone();
+        let behaviour = behaviour.unwrap_or(BotBehaviour::ALL[index % BotBehaviour::ALL.len()]);
+        std::thread::spawn(move || {
+            let mut bot = match futures::executor::block_on(Bot::connect(&addr, behaviour)) {
+                Ok(bot) => bot,
+                Err(e) => {
+                    warn!("Bot {} failed to join: {}", index, e);
+                    return;
+                }
+            };
+            stats.connected.fetch_add(1, Ordering::Relaxed);
+            let mut last_update = std::time::Instant::now();
+            loop {
+                std::thread::sleep(std::time::Duration::from_millis(1000 / 60));
+                let now = std::time::Instant::now();
+                let delta_time = (now - last_update).as_secs_f32();
+                last_update = now;
+                match bot.update(delta_time) {
+                    Ok((updates, events)) => {
+                        stats.updates.fetch_add(updates, Ordering::Relaxed);
+                        stats.events.fetch_add(events, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        warn!("Bot {} stopped: {}", index, e);
+                        break;
+                    }
+                }
+            }
+            stats.connected.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        info!(
+            "{}/{} bots connected, {} updates/s, {} events/s",
+            stats.connected.load(Ordering::Relaxed),
+            count,
+            stats.updates.swap(0, Ordering::Relaxed),
+            stats.events.swap(0, Ordering::Relaxed),
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use super::*;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CreatureType {
+    /// Steals the held item and runs away with it
+    Thief,
+    /// Bites players that come close
+    Crawler,
+}
+
+impl CreatureType {
+    pub fn color(self) -> Color<f32> {
+        match self {
+            Self::Thief => Color::rgb(0.5, 0.3, 0.7),
+            Self::Crawler => Color::rgb(0.3, 0.6, 0.2),
+        }
+    }
+    fn speed(self) -> f32 {
+        match self {
+            Self::Thief => 2.5,
+            Self::Crawler => 1.5,
+        }
+    }
+    /// Pickaxe hits needed to kill
+    fn max_health(self) -> usize {
+        match self {
+            Self::Thief => 2,
+            Self::Crawler => 4,
+        }
+    }
+    /// Picks a creature living at the given depth
+    fn random(depth: f32, rng: &mut impl rand::Rng) -> Option<Self> {
+        if depth < Creature::MIN_DEPTH {
+            None
+        } else if depth < Creature::CRAWLER_DEPTH || rng.gen_bool(0.5) {
+            Some(Self::Thief)
+        } else {
+            Some(Self::Crawler)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Creature {
+    pub id: Id,
+    pub creature_type: CreatureType,
+    pub body: Body,
+    pub health: usize,
+    pub attack_timer: f32,
+    /// Stolen from a player, dropped when killed
+    pub item: Option<Item>,
+    /// Time until a moving creature is sent to the clients again
+    pub sync_timer: f32,
+}
+
+impl Creature {
+    pub const MAX_COUNT: usize = 30;
+    /// Chance to try spawning a creature every tick
+    pub const SPAWN_CHANCE: f64 = 0.1;
+    pub const MIN_DEPTH: f32 = 10.0;
+    pub const CRAWLER_DEPTH: f32 = 40.0;
+    /// Creatures don't appear this close to players
+    pub const SPAWN_DISTANCE: f32 = 8.0;
+    /// Creatures only move when a player is this close
+    pub const SIGHT: f32 = 10.0;
+    pub const ATTACK_RANGE: f32 = 0.7;
+    pub const ATTACK_COOLDOWN: f32 = 1.0;
+    pub const BITE_DAMAGE: f32 = 15.0;
+    /// Seconds between updates of a creature that keeps moving the same way,
+    /// clients move it themselves in between
+    pub const SYNC_INTERVAL: f32 = 0.5;
+
+    pub fn new(id_gen: &mut IdGen, creature_type: CreatureType, position: Vec2<f32>) -> Self {
+        Self {
+            id: id_gen.gen(),
+            creature_type,
+            body: Body::new(position, vec2(0.5, 0.4)),
+            health: creature_type.max_health(),
+            attack_timer: 0.0,
+            item: None,
+            sync_timer: 0.0,
+        }
+    }
+}
+
+impl Model {
+    /// Spawns and moves creatures, returns the events
+    pub(crate) fn tick_creatures(&mut self, delta_time: f32) -> Vec<Event> {
+        let mut events = Vec::new();
+        if self.creatures.len() < Creature::MAX_COUNT && self.rng.gen_bool(Creature::SPAWN_CHANCE) {
+            if let Some(creature) = self.try_spawn_creature() {
+                let event = Event::CreatureUpdated(creature);
+                self.handle_impl(event.clone(), None);
+                events.push(event);
+            }
+        }
+        let mut creature_ids: Vec<Id> = self.creatures.keys().copied().collect();
+        // Sorted so every copy of the model sends the events in the same order
+        creature_ids.sort_by_key(|id| id.raw());
+        for creature_id in creature_ids {
+            for event in self.update_creature(creature_id, delta_time) {
+                self.handle_impl(event.clone(), None);
+                events.push(event);
+            }
+        }
+        events
+    }
+    /// Moves the creatures the way they were last sent, for clients between updates
+    pub fn move_creatures(&mut self, delta_time: f32) {
+        for creature in self.creatures.values_mut() {
+            creature
+                .body
+                .update(&self.tiles, creature.creature_type.speed(), delta_time);
+        }
+    }
+    /// Picks a random spot and spawns a creature there if it is an open cave floor
+    fn try_spawn_creature(&mut self) -> Option<Creature> {
+        let position = vec2(
+            self.rng.gen_range(-WORLD_SIZE..=WORLD_SIZE),
+            self.rng.gen_range(-WORLD_SIZE..0),
+        );
+        let cave_floor = !self.tiles.contains_key(&position)
+            && self
+                .tiles
+                .get(&(position - vec2(0, 1)))
+                .map_or(false, |tile| !tile.can_move_through());
+        if !cave_floor {
+            return None;
+        }
+        let position = position.map(|x| x as f32) + vec2(0.25, 0.0);
+        if self
+            .players
+            .values()
+            .any(|player| (player.position - position).len() < Creature::SPAWN_DISTANCE)
+        {
+            return None;
+        }
+        let creature_type = CreatureType::random(-position.y, &mut self.rng)?;
+        Some(Creature::new(&mut self.id_gen, creature_type, position))
+    }
+    /// Moves the creature and lets it attack, returns the events clients need to follow it
+    fn update_creature(&mut self, creature_id: Id, delta_time: f32) -> Vec<Event> {
+        let mut creature = self.creatures[&creature_id].clone();
+        let old_velocity = creature.body.target_velocity;
+        let target = self
+            .players
+            .values()
+            .map(|player| {
+                (
+                    player,
+                    (player.body().center() - creature.body.center()).len(),
+                )
+            })
+            .filter(|&(_, distance)| distance < Creature::SIGHT)
+            .min_by_key(|&(_, distance)| r32(distance));
+        // Stands still when nobody is there to see it move
+        creature.body.target_velocity = vec2(0.0, 0.0);
+        let mut attack = None;
+        if let Some((player, distance)) = target {
+            let player_id = player.id;
+            let delta = player.body().center() - creature.body.center();
+            let fleeing = creature.creature_type == CreatureType::Thief
+                && (creature.item.is_some() || player.item.is_none());
+            let direction = if fleeing { -delta } else { delta };
+            creature.body.target_velocity = vec2(
+                if direction.x.abs() > 0.1 {
+                    direction.x.signum()
+                } else {
+                    0.0
+                },
+                if direction.y > 0.5 { 1.0 } else { 0.0 },
+            );
+            creature.attack_timer -= delta_time;
+            if distance < Creature::ATTACK_RANGE && creature.attack_timer <= 0.0 {
+                match creature.creature_type {
+                    CreatureType::Thief if !fleeing => {
+                        attack = Some(Event::ItemStolen(player_id, creature_id));
+                    }
+                    CreatureType::Crawler => {
+                        attack = Some(Event::PlayerHurt(player_id, Creature::BITE_DAMAGE));
+                    }
+                    _ => {}
+                }
+                if attack.is_some() {
+                    creature.attack_timer = Creature::ATTACK_COOLDOWN;
+                }
+            }
+        }
+        let old_position = creature.body.position;
+        creature
+            .body
+            .update(&self.tiles, creature.creature_type.speed(), delta_time);
+        creature.sync_timer -= delta_time;
+        let moved = creature.body.position != old_position;
+        let changed = creature.body.target_velocity != old_velocity
+            || attack.is_some()
+            || (moved && creature.sync_timer <= 0.0);
+        if changed {
+            creature.sync_timer = Creature::SYNC_INTERVAL;
+        }
+        self.creatures.insert(creature_id, creature.clone());
+
+        let mut events = Vec::new();
+        if changed {
+            events.push(Event::CreatureUpdated(creature));
+        }
+        // After the update, so the stolen item is not overwritten
+        events.extend(attack);
+        events
+    }
+    /// Hits the creature with a pickaxe, killing it drops its item and some treasure
+    pub(crate) fn hit_creature(&mut self, player_id: Id, creature_id: Id) -> Vec<Event> {
+        let (player, creature) = match (
+            self.players.get(&player_id),
+            self.creatures.get(&creature_id),
+        ) {
+            (Some(player), Some(creature)) => (player, creature),
+            _ => return vec![],
+        };
+        if (player.body().center() - creature.body.center()).len()
+            > Player::RANGE + Player::PICK_UP_TOLERANCE
+        {
+            return vec![];
+        }
+        let mut creature = creature.clone();
+        let mut events = Vec::new();
+        creature.health -= 1;
+        if creature.health > 0 {
+            events.push(Event::CreatureUpdated(creature));
+        } else {
+            events.push(Event::CreatureRemoved(creature_id));
+            let position = creature.body.position;
+            if let Some(mut item) = creature.item {
+                item.position = position;
+                events.push(Event::ItemAdded(item));
+            }
+            let mut treasure = Item::new(&mut self.id_gen, position, ItemType::Chest);
+            treasure.value = (-position.y / 2.0).max(1.0) as usize;
+            events.push(Event::ItemAdded(treasure));
+        }
+        for event in &events {
+            self.handle_impl(event.clone(), None);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A model with a player at the origin standing on a stone floor, and a crawler
+    fn crawler_at(x: f32) -> (Model, Id, Id) {
+        let mut model = Model::new(0);
+        model.creatures.clear();
+        for x in -20..=20 {
+            model.tiles.insert(vec2(x, -1), Tile::Stone);
+            for y in 0..3 {
+                model.tiles.remove(&vec2(x, y));
+            }
+        }
+        let (welcome, _) = model.welcome();
+        let player_id = welcome.player_id.unwrap();
+        model.players.get_mut(&player_id).unwrap().position = vec2(0.0, 0.0);
+        let creature = Creature::new(&mut model.id_gen, CreatureType::Crawler, vec2(x, 0.0));
+        let creature_id = creature.id;
+        model.handle(Event::CreatureUpdated(creature));
+        (model, player_id, creature_id)
+    }
+
+    #[test]
+    fn creatures_walk_to_players_with_few_updates() {
+        let (mut model, _, creature_id) = crawler_at(5.0);
+        let mut updates = 0;
+        for _ in 0..20 {
+            let events = model.update_creature(creature_id, 0.05);
+            updates += events
+                .iter()
+                .filter(|event| matches!(event, Event::CreatureUpdated(_)))
+                .count();
+        }
+        assert!(model.creatures[&creature_id].body.position.x < 4.0);
+        assert!(updates <= 3, "{} updates", updates);
+    }
+
+    #[test]
+    fn crawlers_bite_once_per_cooldown() {
+        let (mut model, player_id, creature_id) = crawler_at(0.3);
+        let bites = |events: Vec<Event>| {
+            events
+                .into_iter()
+                .filter(|event| matches!(event, Event::PlayerHurt(id, _) if *id == player_id))
+                .count()
+        };
+        assert_eq!(bites(model.update_creature(creature_id, 0.05)), 1);
+        assert_eq!(bites(model.update_creature(creature_id, 0.05)), 0);
+    }
+
+    #[test]
+    fn killed_creatures_are_removed_and_drop_treasure() {
+        let (mut model, player_id, creature_id) = crawler_at(1.0);
+        let item_count = model.items.len();
+        for _ in 1..CreatureType::Crawler.max_health() {
+            model.hit_creature(player_id, creature_id);
+            assert!(model.creatures.contains_key(&creature_id));
+        }
+        let events = model.hit_creature(player_id, creature_id);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::CreatureRemoved(id) if *id == creature_id)));
+        assert!(!model.creatures.contains_key(&creature_id));
+        assert_eq!(model.items.len(), item_count + 1);
+    }
+}
@@ -0,0 +1,132 @@
+use super::*;
+
+const POCKETS: Scatter = Scatter {
+    salt: 0,
+    cell_size: 12,
+    chance: 25,
+    radius: 1.5..3.5,
+    stretch: 1.0,
+};
+/// Pockets don't appear closer than this to the surface
+const POCKET_MIN_DEPTH: i32 = 8;
+const LAVA_MIN_DEPTH: i32 = 60;
+
+/// Fluid of the pocket the position is in, if any
+pub(crate) fn generate_pocket(seed: u64, position: Vec2<i32>) -> Option<Tile> {
+    if position.y > -POCKET_MIN_DEPTH {
+        return None;
+    }
+    let (center, hash) = POCKETS.find(seed, position, |_| true)?;
+    Some(if center.y < -LAVA_MIN_DEPTH && (hash >> 32) % 3 == 0 {
+        Tile::Lava
+    } else {
+        Tile::Water
+    })
+}
+
+impl Model {
+    /// Seconds between fluid steps
+    pub const FLUID_INTERVAL: f64 = 0.1;
+    /// Lava only moves every this many fluid steps
+    pub const LAVA_SLOWNESS: usize = 3;
+
+    fn can_flow_into(&self, position: Vec2<i32>) -> bool {
+        position.x.abs() <= WORLD_SIZE
+            && position.y >= -WORLD_SIZE
+            && position.y < 0
+            && !self.tiles.contains_key(&position)
+    }
+    /// Fluids at or next to the position have to check whether they can flow again
+    pub(crate) fn wake_fluids(&mut self, position: Vec2<i32>) {
+        for &delta in &[vec2(0, 0), vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1)] {
+            let neighbour = position + delta;
+            if self.tiles.get(&neighbour).map_or(false, Tile::is_fluid) {
+                self.active_fluids.insert(neighbour);
+            }
+        }
+    }
+    /// Moves every active fluid tile one step, returns the changed tiles
+    pub(crate) fn flow_fluids(&mut self) -> Vec<(Vec2<i32>, Option<Tile>)> {
+        self.fluid_step += 1;
+        let mut changes = Vec::new();
+        for position in bottom_up(mem::replace(&mut self.active_fluids, default())) {
+            let tile = match self.tiles.get(&position) {
+                Some(&tile) if tile.is_fluid() => tile,
+                _ => continue,
+            };
+            if tile == Tile::Lava {
+                let touches_water = [vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1)]
+                    .iter()
+                    .any(|&delta| self.tiles.get(&(position + delta)) == Some(&Tile::Water));
+                if touches_water {
+                    self.set_tile(position, Some(Tile::Stone), &mut changes);
+                    continue;
+                }
+                if self.fluid_step % Self::LAVA_SLOWNESS != 0 {
+                    self.active_fluids.insert(position);
+                    continue;
+                }
+            }
+            let below = position - vec2(0, 1);
+            let target = if self.can_flow_into(below) {
+                Some(below)
+            } else {
+                // Only spreads towards a drop, so puddles settle
+                let direction = if self.rng.gen_bool(0.5) { 1 } else { -1 };
+                [direction, -direction]
+                    .iter()
+                    .map(|&dx| position + vec2(dx, 0))
+                    .find(|&side| self.can_flow_into(side) && self.can_flow_into(side - vec2(0, 1)))
+            };
+            if let Some(target) = target {
+                self.set_tile(position, None, &mut changes);
+                self.set_tile(target, Some(tile), &mut changes);
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty world with a stone floor under the fluid
+    fn basin(fluid: Tile) -> Model {
+        let mut model = Model::new(0);
+        model.tiles = TileMap::new();
+        for x in -3..=3 {
+            model.tiles.insert(vec2(x, -10), Tile::Stone);
+        }
+        model.handle(Event::TilePlaced(vec2(0, -5), fluid));
+        model
+    }
+
+    #[test]
+    fn water_falls_and_settles() {
+        let mut model = basin(Tile::Water);
+        for _ in 0..10 {
+            model.flow_fluids();
+        }
+        assert_eq!(model.tiles.get(&vec2(0, -9)), Some(&Tile::Water));
+        assert_eq!(
+            model
+                .tiles
+                .values()
+                .filter(|&&tile| tile == Tile::Water)
+                .count(),
+            1
+        );
+        assert!(model.active_fluids.is_empty());
+        assert!(model.flow_fluids().is_empty());
+    }
+
+    #[test]
+    fn lava_touching_water_turns_to_stone() {
+        let mut model = basin(Tile::Lava);
+        model.handle(Event::TilePlaced(vec2(1, -5), Tile::Water));
+        let changes = model.flow_fluids();
+        assert!(changes.contains(&(vec2(0, -5), Some(Tile::Stone))));
+        assert_eq!(model.tiles.get(&vec2(0, -5)), Some(&Tile::Stone));
+    }
+}
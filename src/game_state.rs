@@ -2,16 +2,31 @@ use super::*;
 
 struct PlayerState {
     step_animation: f32,
+    interpolation: PlayerInterpolation,
+    chat_bubble: Option<(String, f32)>,
 }
 
 impl PlayerState {
+    const CHAT_BUBBLE_TIME: f32 = 5.0;
+
     pub fn new() -> Self {
         Self {
             step_animation: 0.0,
+            interpolation: PlayerInterpolation::new(),
+            chat_bubble: None,
         }
     }
     pub fn update(&mut self, player: &Player, delta_time: f32) {
         self.step_animation += player.target_velocity.len() * delta_time;
+        if let Some((_, timer)) = &mut self.chat_bubble {
+            *timer -= delta_time;
+            if *timer < 0.0 {
+                self.chat_bubble = None;
+            }
+        }
+    }
+    pub fn say(&mut self, text: String) {
+        self.chat_bubble = Some((text, Self::CHAT_BUBBLE_TIME));
     }
 }
 struct UiState {
@@ -42,13 +57,79 @@ struct UiState {
     customize_character: bool,
     changing_name: bool,
     leaderboard: bool,
+    leaderboard_tab: LeaderboardTab,
+    choosing_team: bool,
+    team_input: String,
+    chatting: bool,
+    chat_input: String,
+    chat_log: std::collections::VecDeque<String>,
 }
 
 const SOUND_RANGE: f32 = 5.0;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LeaderboardTab {
+    Richest,
+    Deepest,
+    MostMined,
+    Chests,
+    Builders,
+    TimePlayed,
+    Teams,
+}
+
+impl LeaderboardTab {
+    const ALL: [Self; 7] = [
+        Self::Richest,
+        Self::Deepest,
+        Self::MostMined,
+        Self::Chests,
+        Self::Builders,
+        Self::TimePlayed,
+        Self::Teams,
+    ];
+    fn name(self) -> &'static str {
+        match self {
+            Self::Richest => "Richest",
+            Self::Deepest => "Deepest",
+            Self::MostMined => "Most mined",
+            Self::Chests => "Chests",
+            Self::Builders => "Builders",
+            Self::TimePlayed => "Time played",
+            Self::Teams => "Teams",
+        }
+    }
+    fn score(self, player: &Player, stats: &PlayerStats, now: f64) -> usize {
+        match self {
+            Self::Richest => player.money,
+            Self::Deepest => stats.max_depth as usize,
+            Self::MostMined => stats.tiles_broken,
+            Self::Chests => stats.chests_found,
+            Self::Builders => stats.blocks_placed,
+            Self::TimePlayed => stats.total_time_played(now),
+            // Teams are ranked separately
+            Self::Teams => player.money,
+        }
+    }
+    fn format_score(self, score: usize) -> String {
+        match self {
+            Self::TimePlayed => format!("{}:{:02}", score / 3600, score / 60 % 60),
+            _ => score.to_string(),
+        }
+    }
+    /// The tab `delta` steps away, wrapping around
+    fn cycle(self, delta: isize) -> Self {
+        let index = Self::ALL.iter().position(|&tab| tab == self).unwrap() as isize;
+        let count = Self::ALL.len() as isize;
+        Self::ALL[((index + delta) % count + count) as usize % Self::ALL.len()]
+    }
+}
+
 impl UiState {
+    const CHAT_LOG_SIZE: usize = 8;
+
     fn locked(&self) -> bool {
-        self.changing_name || self.customize_character || self.leaderboard
+        self.changing_name || self.customize_character || self.leaderboard || self.choosing_team
     }
     fn new(geng: &Rc<Geng>, assets: &Rc<Assets>, player: &Player) -> Self {
         let mut ui_theme = geng::ui::Theme::default(geng);
@@ -84,9 +165,83 @@ impl UiState {
             customize_character: false,
             changing_name: false,
             leaderboard: false,
+            leaderboard_tab: LeaderboardTab::Richest,
+            choosing_team: false,
+            team_input: String::new(),
+            chatting: false,
+            chat_input: String::new(),
+            chat_log: default(),
         }
     }
-    fn ui<'a>(&'a mut self, model: &'a Model, local: bool) -> impl geng::ui::Widget + 'a {
+    fn add_chat_line(&mut self, line: String) {
+        self.chat_log.push_back(line);
+        while self.chat_log.len() > Self::CHAT_LOG_SIZE {
+            self.chat_log.pop_front();
+        }
+    }
+    fn draw_chat(&self, framebuffer: &mut ugli::Framebuffer) {
+        let font = &self.assets.font;
+        let mut position = vec2(50.0, 200.0);
+        if self.chatting {
+            let text = format!("> {}_", self.chat_input);
+            self.geng.draw_2d().quad(
+                framebuffer,
+                AABB::pos_size(
+                    position - vec2(5.0, 5.0),
+                    vec2(font.measure(&text, 30.0).width() + 10.0, 40.0),
+                ),
+                Color::rgba(1.0, 1.0, 1.0, 0.7),
+            );
+            font.draw(framebuffer, &text, position, 30.0, Color::BLACK);
+        }
+        for line in self.chat_log.iter().rev() {
+            position.y += 35.0;
+            font.draw(framebuffer, line, position, 30.0, Color::BLACK);
+        }
+    }
+    fn draw_team_input(&self, framebuffer: &mut ugli::Framebuffer, team: Option<&Team>) {
+        if !self.choosing_team {
+            return;
+        }
+        let font = &self.assets.font;
+        let hint = match team {
+            Some(team) => format!(
+                "Type a team name to switch, or press Enter to leave {}",
+                team.name
+            ),
+            None => "Type a team name to join or create it".to_owned(),
+        };
+        let text = format!("Team: {}_", self.team_input);
+        let center = framebuffer.size().map(|x| x as f32) / 2.0;
+        let width = font
+            .measure(&hint, 30.0)
+            .width()
+            .max(font.measure(&text, 50.0).width());
+        self.geng.draw_2d().quad(
+            framebuffer,
+            AABB::pos_size(
+                center - vec2(width / 2.0 + 20.0, 60.0),
+                vec2(width + 40.0, 140.0),
+            ),
+            Color::rgba(1.0, 1.0, 1.0, 0.7),
+        );
+        font.draw_aligned(framebuffer, &text, center, 0.5, 50.0, Color::BLACK);
+        font.draw_aligned(
+            framebuffer,
+            &hint,
+            center - vec2(0.0, 45.0),
+            0.5,
+            30.0,
+            Color::BLACK,
+        );
+    }
+    /// `me` is the viewer's player, None when spectating
+    fn ui<'a>(
+        &'a mut self,
+        model: &'a Model,
+        local: bool,
+        me: Option<Id>,
+    ) -> impl geng::ui::Widget + 'a {
         use geng::ui;
         use geng::ui::*;
         let font: &Rc<geng::Font> = &self.assets.font;
@@ -120,14 +275,51 @@ impl UiState {
                     .align(vec2(0.5, 0.5)),
                 ));
             } else {
+                let tab = self.leaderboard_tab;
                 let mut column = ui::column(vec![]);
-                let mut players: Vec<_> = model
-                    .leaderboard
-                    .values()
-                    .map(|player| (player.money, &player.name))
-                    .collect();
-                players.sort();
-                players.reverse();
+                let mut tabs = ui::row(vec![]);
+                for &other in LeaderboardTab::ALL.iter() {
+                    let color = if other == tab {
+                        Color::BLUE
+                    } else {
+                        Color::GRAY
+                    };
+                    tabs.push(Box::new(
+                        geng::ui::Text::new(other.name(), font, 30.0, color).uniform_padding(10.0),
+                    ));
+                }
+                column.push(Box::new(tabs));
+                column.push(Box::new(
+                    geng::ui::Text::new("Use Left and Right to switch", font, 20.0, Color::BLACK)
+                        .padding_bottom(20.0),
+                ));
+                let default_stats = PlayerStats::default();
+                let mut players: Vec<_> = match tab {
+                    LeaderboardTab::Teams => model
+                        .teams
+                        .values()
+                        .map(|team| (team.treasury, &team.name, team.id))
+                        .collect(),
+                    _ => model
+                        .leaderboard
+                        .values()
+                        .map(|player| {
+                            let stats = model.stats.get(&player.id).unwrap_or(&default_stats);
+                            (
+                                tab.score(player, stats, model.time),
+                                &player.name,
+                                player.id,
+                            )
+                        })
+                        .collect(),
+                };
+                let me = match tab {
+                    LeaderboardTab::Teams => {
+                        me.and_then(|me| model.team_of(me)).map(|team| team.id)
+                    }
+                    _ => me,
+                };
+                players.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
                 column.push(Box::new(
                     ui::row![
                         geng::ui::Text::new("Rank", font, 30.0, Color::BLACK)
@@ -145,14 +337,25 @@ impl UiState {
                     ]
                     .padding_bottom(50.0),
                 ));
-                for (index, (score, player)) in players.into_iter().take(10).enumerate() {
+                // The viewer is always shown, even outside of the top 10
+                let my_rank = players.iter().position(|&(_, _, id)| Some(id) == me);
+                let mut rows: Vec<usize> = (0..players.len().min(10)).collect();
+                if let Some(my_rank) = my_rank {
+                    if my_rank >= 10 {
+                        rows.push(my_rank);
+                    }
+                }
+                for (index, rank) in rows.into_iter().enumerate() {
+                    let (score, player, id) = players[rank];
+                    let background = if Some(id) == me {
+                        Color::rgba(1.0, 0.9, 0.3, 0.9)
+                    } else {
+                        Color::rgba(1.0, 1.0, 1.0, if index % 2 == 0 { 0.9 } else { 0.0 })
+                    };
                     column.push(Box::new(ui::stack![
-                        geng::ui::ColorBox::new(
-                            &self.geng,
-                            Color::rgba(1.0, 1.0, 1.0, if index % 2 == 0 { 0.9 } else { 0.0 }),
-                        ),
+                        geng::ui::ColorBox::new(&self.geng, background),
                         ui::row![
-                            geng::ui::Text::new((index + 1).to_string(), font, 30.0, Color::BLACK)
+                            geng::ui::Text::new((rank + 1).to_string(), font, 30.0, Color::BLACK)
                                 .align(vec2(1.0, 0.0))
                                 .uniform_padding(10.0)
                                 .fixed_size(vec2(60.0, 50.0)),
@@ -160,7 +363,7 @@ impl UiState {
                                 .align(vec2(0.0, 0.0))
                                 .uniform_padding(10.0)
                                 .fixed_size(vec2(400.0, 50.0)),
-                            geng::ui::Text::new(score.to_string(), font, 30.0, Color::BLACK)
+                            geng::ui::Text::new(tab.format_score(score), font, 30.0, Color::BLACK)
                                 .align(vec2(0.0, 0.0))
                                 .uniform_padding(10.0)
                                 .fixed_size(vec2(100.0, 50.0))
@@ -323,7 +526,13 @@ const HELPS: &[&str] = &[
     "Use Left Mouse Button to swing your pickaxe",
     "Use E to pick up items",
     "Use Q to drop items",
+    "Press T to chat and Enter to send",
     "Use Right Mouse Button to place a block",
+    "Creatures live deep down, hit them with your pickaxe for treasure",
+    "Watch out for lava, and use water to turn it into stone",
+    "Sand and gravel fall when nothing holds them up, so mind your head",
+    "It gets dark deep down, trade a block for a torch and place it to light the way",
+    "Deeper layers are harder to dig, but hide richer treasure",
     "Dig deeper and deeper and you'll get more and more valuable treasure",
     "By the way, music is bad on purpose",
     "But at least ther IS music, right?",
@@ -338,8 +547,12 @@ pub struct GameState {
     model: Model,
     player: Player,
     players: HashMap<Id, PlayerState>,
+    time: f64,
+    prediction: Prediction,
     connection: Connection,
     left_click: Option<Vec2<f32>>,
+    /// Tile being dug and the swings it took so far
+    dig_progress: Option<(Vec2<i32>, usize)>,
     transition: Option<geng::Transition>,
     to_send: Vec<ClientMessage>,
     noise: noise::OpenSimplex,
@@ -348,6 +561,8 @@ pub struct GameState {
     ui_controller: geng::ui::Controller,
     current_help: usize,
     music: Option<geng::SoundEffect>,
+    spectator: Option<Spectator>,
+    kicked: Option<String>,
 }
 
 impl Drop for GameState {
@@ -367,14 +582,76 @@ impl GameState {
         welcome: WelcomeMessage,
         connection: Connection,
     ) -> Self {
+        let player_id = match welcome.player_id {
+            Some(player_id) => player_id,
+            None => {
+                return Self::create(
+                    geng,
+                    assets,
+                    opt,
+                    Self::dummy_player(),
+                    welcome.model,
+                    connection,
+                    Some(Spectator::new(vec2(0.0, 0.0))),
+                )
+            }
+        };
         let player = match player {
             Some(mut player) => {
-                player.id = welcome.player_id;
+                player.id = player_id;
                 player
             }
-            None => welcome.model.players[&welcome.player_id].clone(),
+            None => welcome.model.players[&player_id].clone(),
         };
+        Self::create(geng, assets, opt, player, welcome.model, connection, None)
+    }
+    pub fn replay(
+        geng: &Rc<Geng>,
+        assets: &Rc<Assets>,
+        opt: &Rc<Opt>,
+        recording: Recording,
+    ) -> Self {
+        Self::create(
+            geng,
+            assets,
+            opt,
+            Self::dummy_player(),
+            recording.model,
+            Connection::Replay(Replay::new(recording.events)),
+            Some(Spectator::new(vec2(0.0, 0.0))),
+        )
+    }
+    /// Nobody controls this player, it only fills the slot of the local one when spectating
+    fn dummy_player() -> Player {
+        Player::new(&mut IdGen::new(), &mut global_rng())
+    }
+    /// The local player, None when spectating since the dummy player's id belongs to someone else
+    fn viewer(&self) -> Option<Id> {
+        match self.spectator {
+            Some(_) => None,
+            None => Some(self.player.id),
+        }
+    }
+    fn create(
+        geng: &Rc<Geng>,
+        assets: &Rc<Assets>,
+        opt: &Rc<Opt>,
+        player: Player,
+        mut model: Model,
+        connection: Connection,
+        spectator: Option<Spectator>,
+    ) -> Self {
+        model.enable_light();
         let ui_state = UiState::new(geng, assets, &player);
+        let mut players = HashMap::new();
+        for other in model.players.values() {
+            if spectator.is_none() && other.id == player.id {
+                continue;
+            }
+            let mut state = PlayerState::new();
+            state.interpolation.push(model.time, other.clone());
+            players.insert(other.id, state);
+        }
         Self {
             geng: geng.clone(),
             assets: assets.clone(),
@@ -382,10 +659,13 @@ impl GameState {
             camera: Camera::new(10.0),
             renderer: Renderer::new(geng),
             player,
-            players: HashMap::new(),
-            model: welcome.model,
+            players,
+            time: 0.0,
+            prediction: Prediction::new(),
+            model,
             connection,
             left_click: None,
+            dig_progress: None,
             transition: None,
             to_send: Vec::new(),
             noise: noise::OpenSimplex::new(),
@@ -394,6 +674,17 @@ impl GameState {
             ui_controller: geng::ui::Controller::new(),
             current_help: HELPS.len(),
             music: None,
+            spectator,
+            kicked: None,
+        }
+    }
+    fn is_me(&self, player_id: Id) -> bool {
+        self.spectator.is_none() && player_id == self.player.id
+    }
+    fn view_center(&self) -> Vec2<f32> {
+        match &self.spectator {
+            Some(spectator) => spectator.position,
+            None => self.player.position,
         }
     }
     fn draw_player_part(
@@ -407,7 +698,7 @@ impl GameState {
         scale: f32,
         color: Color<f32>,
     ) {
-        self.renderer.draw(
+        self.renderer.draw_lit(
             framebuffer,
             &self.camera,
             player.matrix()
@@ -419,10 +710,20 @@ impl GameState {
                 * Mat4::translate(vec3(-0.5, -0.5, 0.0)),
             texture,
             color,
+            self.light_at(player.body().center()),
         );
     }
+    /// Light level at the point, never completely dark so outlines can still be made out
+    fn light_at(&self, position: Vec2<f32>) -> f32 {
+        const MIN_LIGHT: f32 = 0.05;
+        self.model
+            .light
+            .as_ref()
+            .map_or(1.0, |light| light.get(position.map(|x| x.floor() as i32)))
+            .max(MIN_LIGHT)
+    }
     fn draw_item(&self, framebuffer: &mut ugli::Framebuffer, item: &Item) {
-        self.renderer.draw(
+        self.renderer.draw_lit(
             framebuffer,
             &self.camera,
             Mat4::translate(item.position.extend(0.0))
@@ -430,8 +731,45 @@ impl GameState {
                 * Mat4::translate(vec3(-0.5, 0.0, 0.0)),
             self.assets.item_texture(item.item_type),
             item.item_type.color(),
+            self.light_at(item.position + vec2(0.0, Item::SIZE / 2.0)),
         );
     }
+    fn draw_creature(&self, framebuffer: &mut ugli::Framebuffer, creature: &Creature) {
+        let matrix = creature.body.matrix()
+            * Mat4::translate(vec3(-1.0, -1.0, 0.0))
+            * Mat4::scale_uniform(3.0);
+        let light = self.light_at(creature.body.center());
+        self.renderer.draw_lit(
+            framebuffer,
+            &self.camera,
+            matrix,
+            &self.assets.body,
+            creature.creature_type.color(),
+            light,
+        );
+        if let Some(texture) = self.assets.eye.first() {
+            self.renderer.draw_lit(
+                framebuffer,
+                &self.camera,
+                matrix,
+                texture,
+                Color::WHITE,
+                light,
+            );
+        }
+        if let Some(item) = &creature.item {
+            self.renderer.draw_lit(
+                framebuffer,
+                &self.camera,
+                creature.body.matrix()
+                    * Mat4::translate(vec3(0.0, 1.0, 0.0))
+                    * Mat4::scale_uniform(Item::SIZE / creature.body.size.x),
+                self.assets.item_texture(item.item_type),
+                item.item_type.color(),
+                light,
+            );
+        }
+    }
     fn draw_player(&self, framebuffer: &mut ugli::Framebuffer, player: &Player) {
         let state = if let Some(state) = self.players.get(&player.id) {
             state
@@ -560,7 +898,13 @@ impl GameState {
                 false,
                 0.0,
                 1.0,
-                hsv(player.hat_color as f32, 0.5, 0.7),
+                hsv(
+                    self.model
+                        .team_of(player.id)
+                        .map_or(player.hat_color, |team| team.color) as f32,
+                    0.5,
+                    0.7,
+                ),
             );
         }
         if let Some(texture) = self.assets.ear.get(player.ear) {
@@ -617,6 +961,9 @@ impl GameState {
             if changing_name {
                 text.push('_');
             }
+            if let Some(team) = self.model.team_of(player.id) {
+                text = format!("[{}] {}", team.name, text);
+            }
             let pos = self.camera.world_to_screen(
                 framebuffer.size().map(|x| x as f32),
                 player.position + vec2(player.size.x / 2.0, player.size.y * 2.0),
@@ -634,6 +981,28 @@ impl GameState {
             );
             font.draw_aligned(framebuffer, &text, pos, 0.5, size, Color::BLACK);
         }
+        let chat_bubble = self
+            .players
+            .get(&player.id)
+            .and_then(|state| state.chat_bubble.as_ref());
+        if let Some((text, _)) = chat_bubble {
+            let pos = self.camera.world_to_screen(
+                framebuffer.size().map(|x| x as f32),
+                player.position + vec2(player.size.x / 2.0, player.size.y * 3.0),
+            );
+            let font = &self.assets.font;
+            let size = framebuffer.size().y as f32 / self.camera.fov / 3.0;
+            let text_width = font.measure(text, size).width();
+            self.geng.draw_2d().quad(
+                framebuffer,
+                AABB::pos_size(
+                    pos - vec2(text_width / 2.0 + 5.0, 5.0),
+                    vec2(text_width + 10.0, size + 10.0),
+                ),
+                Color::rgba(1.0, 1.0, 0.8, 0.9),
+            );
+            font.draw_aligned(framebuffer, text, pos, 0.5, size, Color::BLACK);
+        }
     }
     fn draw_tile(
         &self,
@@ -642,12 +1011,14 @@ impl GameState {
         texture: &ugli::Texture,
         color: Color<f32>,
     ) {
-        self.renderer.draw(
+        let position = position.map(|x| x as f32);
+        self.renderer.draw_lit(
             framebuffer,
             &self.camera,
-            Mat4::translate(position.map(|x| x as f32).extend(0.0)),
+            Mat4::translate(position.extend(0.0)),
             &texture,
             color,
+            self.light_at(position),
         );
     }
     fn draw_random_tile(
@@ -672,7 +1043,7 @@ impl GameState {
     fn update_player(&mut self, delta_time: f32) {
         self.ui_state.update_player(&mut self.player);
         self.player.target_velocity = vec2(0.0, 0.0);
-        if !self.ui_state.locked() {
+        if !self.ui_state.locked() && !self.ui_state.chatting {
             if self.geng.window().is_key_pressed(geng::Key::A)
                 || self.geng.window().is_key_pressed(geng::Key::Left)
             {
@@ -695,18 +1066,55 @@ impl GameState {
             }
         }
         self.player.update(&self.model.tiles, delta_time);
-        if let Some(position) = self.left_click {
-            let position = position.map(|x| x.floor() as i32);
+        if self.player.is_dead() {
+            // The server drops the item where it last saw the player
+            self.player.item = None;
+            self.player.respawn();
+            self.to_send.push(ClientMessage::Respawn);
+            self.ui_state.add_chat_line("You died".to_owned());
+        }
+        if let Some(click) = self.left_click {
+            let position = click.map(|x| x.floor() as i32);
+            let creature = self.model.creatures.values().find(|creature| {
+                AABB::pos_size(creature.body.position, creature.body.size).contains(click)
+                    && (creature.body.center() - self.player.body().center()).len() < Player::RANGE
+            });
             match self.player.swing {
                 None => self.player.swing = Some(0.0),
                 Some(swing) if swing > 1.0 => {
-                    if ((self.player.position + self.player.size / 2.0)
+                    if let Some(creature) = creature {
+                        let mut effect = self.assets.dig.effect();
+                        effect.set_volume(self.ui_state.volume);
+                        effect.play();
+                        self.to_send.push(ClientMessage::Hit(creature.id));
+                    } else if ((self.player.position + self.player.size / 2.0)
                         - position.map(|x| x as f32 + 0.5))
                     .len()
                         < Player::RANGE
+                        && self
+                            .model
+                            .tiles
+                            .get(&position)
+                            .map_or(false, |tile| !tile.is_fluid())
                     {
-                        self.to_send
-                            .push(ClientMessage::Event(Event::TileBroken(position)));
+                        let mut effect = self.assets.dig.effect();
+                        effect.set_volume(self.ui_state.volume);
+                        effect.play();
+                        let hits = match self.dig_progress {
+                            Some((target, hits)) if target == position => hits + 1,
+                            _ => 1,
+                        };
+                        if hits >= self.model.tiles[&position].hardness() {
+                            self.dig_progress = None;
+                            let message = self.prediction.predict(
+                                &mut self.model,
+                                Event::TileBroken(position),
+                                None,
+                            );
+                            self.to_send.push(message);
+                        } else {
+                            self.dig_progress = Some((position, hits));
+                        }
                     }
                     self.player.swing = Some(0.0);
                 }
@@ -719,7 +1127,10 @@ impl GameState {
 
     fn draw_impl(&mut self, framebuffer: &mut ugli::Framebuffer) {
         self.framebuffer_size = framebuffer.size().map(|x| x as f32);
-        if self.ui_state.locked() {
+        if let Some(spectator) = &self.spectator {
+            self.camera.target_position = spectator.position;
+            self.camera.target_fov = spectator.fov();
+        } else if self.ui_state.locked() {
             self.camera.target_position = self.player.position;
             self.camera.target_fov = 3.0;
         } else {
@@ -736,6 +1147,9 @@ impl GameState {
             Color::rgba(1.0, 1.0, 1.0, 0.5),
         );
         const VIEW_RADIUS: i32 = 12;
+        let view_center = self.view_center();
+        let half_width = self.camera.fov * self.framebuffer_size.x / self.framebuffer_size.y / 2.0;
+        let view_radius = VIEW_RADIUS.max(half_width.ceil() as i32 + 1);
         for shop in &self.model.shops {
             match shop.shop_type {
                 ShopType::Sell {
@@ -807,7 +1221,7 @@ impl GameState {
                         Color::WHITE,
                     );
                 }
-                ShopType::Train => {
+                ShopType::Train(_) => {
                     self.renderer.draw(
                         framebuffer,
                         &self.camera,
@@ -825,6 +1239,15 @@ impl GameState {
                         Color::WHITE,
                     );
                 }
+                ShopType::Team => {
+                    self.renderer.draw(
+                        framebuffer,
+                        &self.camera,
+                        Mat4::translate(vec3(shop.position, 0.0, 0.0)) * Mat4::scale_uniform(2.0),
+                        &self.assets.house,
+                        Color::rgb(0.6, 0.8, 1.0),
+                    );
+                }
                 ShopType::LeaderBoard => {
                     self.renderer.draw(
                         framebuffer,
@@ -845,17 +1268,11 @@ impl GameState {
                 }
             }
         }
-        for x in self.player.position.x as i32 - VIEW_RADIUS
-            ..=self.player.position.x as i32 + VIEW_RADIUS
-        {
+        for x in view_center.x as i32 - view_radius..=view_center.x as i32 + view_radius {
             self.draw_tile(framebuffer, vec2(x, 0), &self.assets.grass, Color::WHITE);
         }
-        for x in self.player.position.x as i32 - VIEW_RADIUS
-            ..=self.player.position.x as i32 + VIEW_RADIUS
-        {
-            for y in self.player.position.y as i32 - VIEW_RADIUS
-                ..=self.player.position.y as i32 + VIEW_RADIUS
-            {
+        for x in view_center.x as i32 - view_radius..=view_center.x as i32 + view_radius {
+            for y in view_center.y as i32 - view_radius..=view_center.y as i32 + view_radius {
                 let position = vec2(x, y);
                 let mut draw_background = true;
                 let current_tile = self.model.tiles.get(&position);
@@ -865,15 +1282,12 @@ impl GameState {
                     }
                 }
                 if y < 0 && draw_background {
+                    let biome = Biome::at_depth(-y);
                     self.draw_random_tile(
                         framebuffer,
                         position,
-                        if y == -1 {
-                            &self.assets.dirt
-                        } else {
-                            &self.assets.stone
-                        },
-                        Color::GRAY,
+                        self.assets.tile_textures(biome.tile()),
+                        biome.background_color(),
                         100.0,
                     );
                 }
@@ -882,22 +1296,22 @@ impl GameState {
                         framebuffer,
                         position,
                         self.assets.tile_textures(*tile),
-                        if *tile == Tile::Block {
-                            Color::rgb(0.8, 0.8, 0.8)
-                        } else {
-                            Color::WHITE
+                        match tile {
+                            Tile::Block => Color::rgb(0.8, 0.8, 0.8),
+                            Tile::Water => Color::rgba(0.2, 0.4, 1.0, 0.6),
+                            Tile::Lava => Color::rgb(1.0, 0.4, 0.1),
+                            Tile::Sand => Color::rgb(1.0, 0.9, 0.5),
+                            Tile::Gravel => Color::rgb(0.6, 0.6, 0.65),
+                            Tile::Torch => ItemType::Torch.color(),
+                            _ => Color::WHITE,
                         },
                         0.0,
                     );
                 }
             }
         }
-        for x in self.player.position.x as i32 - VIEW_RADIUS
-            ..=self.player.position.x as i32 + VIEW_RADIUS
-        {
-            for y in self.player.position.y as i32 - VIEW_RADIUS
-                ..=self.player.position.y as i32 + VIEW_RADIUS
-            {
+        for x in view_center.x as i32 - view_radius..=view_center.x as i32 + view_radius {
+            for y in view_center.y as i32 - view_radius..=view_center.y as i32 + view_radius {
                 let position = vec2(x, y);
                 let current_tile = self.model.tiles.get(&position);
                 let right_tile = self.model.tiles.get(&(position + vec2(1, 0)));
@@ -930,7 +1344,7 @@ impl GameState {
                 }
             }
         }
-        {
+        if self.spectator.is_none() {
             let position = self
                 .camera
                 .screen_to_world(
@@ -942,7 +1356,17 @@ impl GameState {
                 .len()
                 < Player::RANGE
             {
-                if self.model.tiles.get(&position).is_some() {
+                if let Some(tile) = self.model.tiles.get(&position) {
+                    // Darker the closer the tile is to breaking
+                    let color = match self.dig_progress {
+                        Some((target, hits)) if target == position => Color::rgba(
+                            0.0,
+                            0.0,
+                            0.0,
+                            0.5 + 0.5 * hits as f32 / tile.hardness() as f32,
+                        ),
+                        _ => Color::rgba(0.0, 0.0, 0.0, 0.5),
+                    };
                     self.renderer.draw(
                         framebuffer,
                         &self.camera,
@@ -953,7 +1377,7 @@ impl GameState {
                         )) * Mat4::rotate_z(f32::PI / 4.0)
                             * Mat4::translate(vec3(-0.5, -0.5, 0.0)),
                         &self.assets.border,
-                        Color::rgba(0.0, 0.0, 0.0, 0.5),
+                        color,
                     );
                     self.renderer.draw(
                         framebuffer,
@@ -965,7 +1389,7 @@ impl GameState {
                         )) * Mat4::rotate_z(-f32::PI / 4.0)
                             * Mat4::translate(vec3(-0.5, -0.5, 0.0)),
                         &self.assets.border,
-                        Color::rgba(0.0, 0.0, 0.0, 0.5),
+                        color,
                     );
                 }
             }
@@ -997,18 +1421,39 @@ impl GameState {
             }
         }
         for item in self.model.items.values() {
-            let delta_pos = item.position - self.player.position;
+            let delta_pos = item.position - view_center;
             let distance = delta_pos.x.abs().max(delta_pos.y.abs());
-            if distance < VIEW_RADIUS as f32 {
+            if distance < view_radius as f32 {
                 self.draw_item(framebuffer, item);
             }
         }
-        self.draw_player(framebuffer, &self.player);
+        for creature in self.model.creatures.values() {
+            let delta_pos = creature.body.position - view_center;
+            if delta_pos.x.abs().max(delta_pos.y.abs()) < view_radius as f32 {
+                self.draw_creature(framebuffer, creature);
+            }
+        }
+        if self.spectator.is_none() {
+            self.draw_player(framebuffer, &self.player);
+        }
         for player in self.model.players.values() {
-            if player.id == self.player.id {
+            if self.is_me(player.id) {
                 continue;
             }
-            self.draw_player(framebuffer, player);
+            if let Some(player) = self
+                .players
+                .get(&player.id)
+                .and_then(|state| state.interpolation.get(self.model.time))
+            {
+                self.draw_player(framebuffer, &player);
+            }
+        }
+        if self.spectator.is_some() {
+            self.left_click = None;
+            self.draw_spectator_hud(framebuffer);
+            self.draw_round(framebuffer);
+            self.ui_state.draw_chat(framebuffer);
+            return;
         }
         if self
             .geng
@@ -1040,6 +1485,24 @@ impl GameState {
             Color::WHITE,
         );
         font.draw(framebuffer, &text, vec2(150.0, 50.0), 100.0, Color::BLACK);
+        self.draw_health(framebuffer);
+        self.draw_depth(framebuffer);
+        if let Some(team) = self.model.team_of(self.player.id) {
+            let text = format!("Team {}: {}", team.name, team.treasury);
+            self.geng.draw_2d().quad(
+                framebuffer,
+                AABB::pos_size(
+                    vec2(40.0, 150.0),
+                    vec2(font.measure(&text, 40.0).width() + 20.0, 50.0),
+                ),
+                Color::rgba(1.0, 1.0, 1.0, 0.7),
+            );
+            font.draw(framebuffer, &text, vec2(50.0, 155.0), 40.0, Color::BLACK);
+        }
+        self.draw_round(framebuffer);
+        self.ui_state
+            .draw_team_input(framebuffer, self.model.team_of(self.player.id));
+        self.ui_state.draw_chat(framebuffer);
         if !self.ui_state.locked() {
             let shop = self.model.shops.iter().find(|shop| {
                 AABB::pos_size(vec2(shop.position, 0.0) - self.player.size, vec2(2.0, 2.0))
@@ -1052,14 +1515,283 @@ impl GameState {
                     40.0,
                     shop.help(),
                 );
-                if matches!(shop.shop_type, ShopType::Info) {
-                    if let Some(text) = HELPS.get(self.current_help) {
-                        self.draw_text(framebuffer, vec2(1.0 + shop.position, 2.5), 30.0, text);
+                match &shop.shop_type {
+                    ShopType::Info => {
+                        if let Some(text) = HELPS.get(self.current_help) {
+                            self.draw_text(framebuffer, vec2(1.0 + shop.position, 2.5), 30.0, text);
+                        }
                     }
+                    ShopType::Train(Some(world)) => {
+                        self.draw_text(
+                            framebuffer,
+                            vec2(1.0 + shop.position, 2.5),
+                            30.0,
+                            &format!("Next stop: {}", world),
+                        );
+                    }
+                    _ => {}
                 }
             }
         }
     }
+    fn draw_spectator_hud(&self, framebuffer: &mut ugli::Framebuffer) {
+        let mut text = match &self.connection {
+            Connection::Replay(replay) => {
+                let mut text = format!("Replay {:.0}s x{}", replay.time, replay.speed);
+                if replay.finished() {
+                    text.push_str(" (finished)");
+                } else if replay.paused {
+                    text.push_str(" (paused)");
+                }
+                text
+            }
+            _ => "Spectating".to_owned(),
+        };
+        let following = self
+            .spectator
+            .as_ref()
+            .and_then(|spectator| spectator.following)
+            .and_then(|player_id| self.model.players.get(&player_id));
+        if let Some(player) = following {
+            text = format!("{} - following {}", text, player.name);
+        }
+        let font = &self.assets.font;
+        font.draw(
+            framebuffer,
+            "Tab - follow next player, O - overview",
+            vec2(50.0, 120.0),
+            30.0,
+            Color::BLACK,
+        );
+        self.geng.draw_2d().quad(
+            framebuffer,
+            AABB::pos_size(
+                vec2(40.0, 40.0),
+                vec2(font.measure(&text, 50.0).width() + 20.0, 70.0),
+            ),
+            Color::rgba(1.0, 1.0, 1.0, 0.7),
+        );
+        font.draw(framebuffer, &text, vec2(50.0, 50.0), 50.0, Color::BLACK);
+    }
+    fn draw_health(&self, framebuffer: &mut ugli::Framebuffer) {
+        let size = vec2(300.0, 30.0);
+        let position = vec2(50.0, framebuffer.size().y as f32 - 50.0 - size.y);
+        self.geng.draw_2d().quad(
+            framebuffer,
+            AABB::pos_size(position - vec2(5.0, 5.0), size + vec2(10.0, 10.0)),
+            Color::rgba(1.0, 1.0, 1.0, 0.7),
+        );
+        self.geng.draw_2d().quad(
+            framebuffer,
+            AABB::pos_size(
+                position,
+                vec2(size.x * self.player.health / Player::MAX_HEALTH, size.y),
+            ),
+            Color::rgb(0.8, 0.1, 0.1),
+        );
+    }
+    /// Layer the player is in and how deep, under the health bar
+    fn draw_depth(&self, framebuffer: &mut ugli::Framebuffer) {
+        let depth = -self.player.position.y.floor() as i32;
+        let text = if depth > 0 {
+            format!("{}, depth {}", Biome::at_depth(depth).name(), depth)
+        } else {
+            "Surface".to_owned()
+        };
+        let font = &self.assets.font;
+        let position = vec2(50.0, framebuffer.size().y as f32 - 140.0);
+        self.geng.draw_2d().quad(
+            framebuffer,
+            AABB::pos_size(
+                position - vec2(5.0, 5.0),
+                vec2(font.measure(&text, 40.0).width() + 10.0, 50.0),
+            ),
+            Color::rgba(1.0, 1.0, 1.0, 0.7),
+        );
+        font.draw(framebuffer, &text, position, 40.0, Color::BLACK);
+    }
+    fn draw_round(&self, framebuffer: &mut ugli::Framebuffer) {
+        let round = match &self.model.round {
+            Some(round) => round,
+            None => return,
+        };
+        let seconds = round.time_left.ceil() as usize;
+        let text = format!(
+            "Round {} - {}:{:02}",
+            round.number,
+            seconds / 60,
+            seconds % 60
+        );
+        let font = &self.assets.font;
+        let width = font.measure(&text, 50.0).width();
+        let position = vec2(
+            framebuffer.size().x as f32 / 2.0,
+            framebuffer.size().y as f32 - 80.0,
+        );
+        self.geng.draw_2d().quad(
+            framebuffer,
+            AABB::pos_size(
+                position - vec2(width / 2.0 + 10.0, 10.0),
+                vec2(width + 20.0, 70.0),
+            ),
+            Color::rgba(1.0, 1.0, 1.0, 0.7),
+        );
+        let color = if round.time_left < 30.0 {
+            Color::RED
+        } else {
+            Color::BLACK
+        };
+        font.draw_aligned(framebuffer, &text, position, 0.5, 50.0, color);
+    }
+    fn handle_spectator_event(&mut self, event: geng::Event) {
+        let spectator = match &mut self.spectator {
+            Some(spectator) => spectator,
+            None => return,
+        };
+        match event {
+            geng::Event::Wheel { delta } => spectator.zoom(delta),
+            geng::Event::KeyDown {
+                key: geng::Key::Tab,
+                ..
+            } => {
+                spectator.follow_next(self.model.players.keys().copied());
+            }
+            geng::Event::KeyDown {
+                key: geng::Key::O, ..
+            } => {
+                spectator.overview = !spectator.overview;
+            }
+            geng::Event::KeyDown { key, .. } => {
+                if let Connection::Replay(replay) = &mut self.connection {
+                    match key {
+                        geng::Key::Space => replay.paused = !replay.paused,
+                        geng::Key::Z => {
+                            replay.speed = (replay.speed / 2.0).max(1.0 / Replay::MAX_SPEED);
+                        }
+                        geng::Key::X => {
+                            replay.speed = (replay.speed * 2.0).min(Replay::MAX_SPEED);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    fn handle_server_message(&mut self, message: ServerMessage) {
+        match message {
+            ServerMessage::Update(events) => {
+                for event in events {
+                    match event {
+                        Event::TilePlaced(position, ..)
+                            if !self.model.tiles.contains_key(&position) =>
+                        {
+                            if (position.map(|x| x as f32) - self.view_center()).len() < SOUND_RANGE
+                            {
+                                let mut effect = self.assets.place.effect();
+                                effect.set_volume(self.ui_state.volume);
+                                effect.play();
+                            }
+                        }
+                        Event::TileBroken(position, ..)
+                            if self.model.tiles.contains_key(&position) =>
+                        {
+                            if (position.map(|x| x as f32) - self.view_center()).len() < SOUND_RANGE
+                            {
+                                let mut effect = self.assets.dig.effect();
+                                effect.set_volume(self.ui_state.volume);
+                                effect.play();
+                            }
+                        }
+                        // Joined players are shown from their first update on
+                        Event::PlayerUpdated(ref player, time) if !self.is_me(player.id) => {
+                            self.players
+                                .entry(player.id)
+                                .or_default()
+                                .interpolation
+                                .push(time, player.clone());
+                        }
+                        Event::PlayerLeft(player_id) => {
+                            self.players.remove(&player_id);
+                        }
+                        Event::Chat(player_id, ref text) => {
+                            let name = match self.model.players.get(&player_id) {
+                                Some(player) if !player.name.is_empty() => player.name.clone(),
+                                _ => "???".to_owned(),
+                            };
+                            self.ui_state.add_chat_line(format!("{}: {}", name, text));
+                            self.players.entry(player_id).or_default().say(text.clone());
+                        }
+                        Event::Announcement(ref text) => {
+                            self.ui_state.add_chat_line(format!("[Server] {}", text));
+                        }
+                        Event::PlayerHurt(player_id, damage) if self.is_me(player_id) => {
+                            self.player.damage(damage);
+                        }
+                        Event::ItemStolen(player_id, _) if self.is_me(player_id) => {
+                            if self.player.item.take().is_some() {
+                                self.ui_state
+                                    .add_chat_line("A thief stole your item!".to_owned());
+                            }
+                        }
+                        _ => {}
+                    }
+                    self.prediction.handle(&mut self.model, event);
+                }
+            }
+            ServerMessage::Confirmed(seq) => {
+                self.prediction.acknowledge(&mut self.model, seq, true);
+            }
+            ServerMessage::Rejected(seq) => {
+                let item = self.prediction.acknowledge(&mut self.model, seq, false);
+                self.rollback(item);
+            }
+            ServerMessage::PickedUp(item) => {
+                self.prediction
+                    .acknowledge_pick_up(&mut self.model, item.id, true);
+                if self.player.item.is_none() {
+                    self.player.item = Some(item);
+                } else {
+                    self.rollback(Some(item));
+                }
+            }
+            ServerMessage::PickUpFailed(item_id) => {
+                self.prediction
+                    .acknowledge_pick_up(&mut self.model, item_id, false);
+            }
+            ServerMessage::Kicked(reason) => {
+                warn!("Kicked from the server: {}", reason);
+                self.kicked = Some(reason);
+            }
+            // The server started a new round in a new world
+            ServerMessage::Welcome(welcome) => {
+                self.model = welcome.model;
+                self.model.enable_light();
+                self.prediction = Prediction::new();
+                self.players.clear();
+                let player = welcome
+                    .player_id
+                    .and_then(|player_id| self.model.players.get(&player_id))
+                    .cloned();
+                if let Some(player) = player {
+                    self.to_send.push(ClientMessage::Welcomed);
+                    self.player.position = player.position;
+                    self.player.target_velocity = player.target_velocity;
+                    self.player.item = player.item;
+                    self.player.money = player.money;
+                }
+            }
+        }
+    }
+    fn rollback(&mut self, item: Option<Item>) {
+        if let Some(item) = item {
+            // Only the held item can be dropped, so it makes room for the returned one
+            if self.player.item.is_some() {
+                self.to_send.push(ClientMessage::DropItem);
+            }
+            self.player.item = Some(item);
+        }
+    }
     fn draw_text(
         &self,
         framebuffer: &mut ugli::Framebuffer,
@@ -1088,19 +1820,54 @@ impl geng::State for GameState {
     fn draw(&mut self, framebuffer: &mut ugli::Framebuffer) {
         self.draw_impl(framebuffer);
         self.ui_controller.draw(
-            &mut self.ui_state.ui(&self.model, self.connection.is_local()),
+            &mut self
+                .ui_state
+                .ui(&self.model, self.connection.is_local(), self.viewer()),
             framebuffer,
         );
+        if let Some(reason) = &self.kicked {
+            let framebuffer_size = framebuffer.size().map(|x| x as f32);
+            self.geng.draw_2d().quad(
+                framebuffer,
+                AABB::pos_size(vec2(0.0, 0.0), framebuffer_size),
+                Color::rgba(1.0, 1.0, 1.0, 0.8),
+            );
+            self.assets.font.draw_aligned(
+                framebuffer,
+                &format!("Disconnected: {}", reason),
+                framebuffer_size / 2.0,
+                0.5,
+                40.0,
+                Color::BLACK,
+            );
+            self.assets.font.draw_aligned(
+                framebuffer,
+                "Press Escape to leave",
+                framebuffer_size / 2.0 - vec2(0.0, 50.0),
+                0.5,
+                30.0,
+                Color::BLACK,
+            );
+        }
     }
     fn update(&mut self, delta_time: f64) {
         if let Some(music) = &mut self.music {
             music.set_volume(self.ui_state.volume * 0.3);
         }
+        self.time += delta_time;
         self.camera.update(delta_time as f32);
         self.ui_controller.update(
-            &mut self.ui_state.ui(&self.model, self.connection.is_local()),
+            &mut self
+                .ui_state
+                .ui(&self.model, self.connection.is_local(), self.viewer()),
             delta_time,
         );
+        // Only the server ticks the world, this keeps the clocks smooth between updates
+        self.model.time += delta_time;
+        if let Some(round) = &mut self.model.round {
+            round.time_left = (round.time_left - delta_time).max(0.0);
+        }
+        self.model.move_creatures(delta_time as f32);
         let mut messages = Vec::new();
         match &mut self.connection {
             Connection::Remote(connection) => messages.extend(connection.new_messages()),
@@ -1111,13 +1878,26 @@ impl geng::State for GameState {
                     *next_tick += 1.0 / model.ticks_per_second;
                 }
             }
+            Connection::Replay(replay) => {
+                messages.extend(replay.update(delta_time));
+            }
+        }
+        // Handled before taking the snapshot below, so a new round's welcome
+        // resets the player before its state is sent
+        let received = !messages.is_empty();
+        for message in messages {
+            self.handle_server_message(message);
         }
         let mut messages_to_send = mem::replace(&mut self.to_send, Vec::new());
-        if !messages.is_empty() {
-            messages_to_send.push(ClientMessage::Event(Event::PlayerUpdated(
-                self.player.clone(),
-            )));
+        if received {
+            messages_to_send.push(match self.spectator {
+                Some(_) => ClientMessage::Poll,
+                None => {
+                    ClientMessage::Event(Event::PlayerUpdated(self.player.clone(), self.model.time))
+                }
+            });
         }
+        let mut replies = Vec::new();
         for message in messages_to_send {
             match &mut self.connection {
                 Connection::Remote(connection) => connection.send(message),
@@ -1125,64 +1905,53 @@ impl geng::State for GameState {
                     next_tick: _,
                     model,
                 } => {
-                    messages.push(ServerMessage::Update(
-                        model.handle_message(self.player.id, message),
-                    ));
+                    let (events, local_replies) = model.handle_message(self.player.id, message);
+                    replies.push(ServerMessage::Update(events));
+                    replies.extend(local_replies);
                 }
+                Connection::Replay(_) => {}
             }
         }
-        for message in messages {
-            match message {
-                ServerMessage::Update(events) => {
-                    for event in events {
-                        match event {
-                            Event::TilePlaced(position, ..) => {
-                                if (position.map(|x| x as f32) - self.player.position).len()
-                                    < SOUND_RANGE
-                                {
-                                    let mut effect = self.assets.place.effect();
-                                    effect.set_volume(self.ui_state.volume);
-                                    effect.play();
-                                }
-                            }
-                            Event::TileBroken(position, ..)
-                                if self.model.tiles.contains_key(&position) =>
-                            {
-                                if (position.map(|x| x as f32) - self.player.position).len()
-                                    < SOUND_RANGE
-                                {
-                                    let mut effect = self.assets.dig.effect();
-                                    effect.set_volume(self.ui_state.volume);
-                                    effect.play();
-                                }
-                            }
-                            _ => {}
-                        }
-                        self.model.handle(event);
-                    }
-                }
-                _ => unreachable!(),
-            }
+        for message in replies {
+            self.handle_server_message(message);
         }
         let delta_time = delta_time as f32;
-        self.update_player(delta_time);
-        for player in self.model.players.values_mut() {
-            player.update(&self.model.tiles, delta_time);
+        match &mut self.spectator {
+            Some(spectator) => spectator.update(&self.geng, delta_time),
+            None => self.update_player(delta_time),
         }
 
-        for player in self.model.players.values() {
-            if player.id == self.player.id {
+        let me = self.viewer();
+        for (&player_id, state) in &mut self.players {
+            if Some(player_id) == me {
                 continue;
             }
-            self.players
-                .entry(player.id)
-                .or_default()
-                .update(player, delta_time);
-        }
-        self.players
-            .entry(self.player.id)
-            .or_default()
-            .update(&self.player, delta_time);
+            state.interpolation.update(self.model.time, delta_time);
+            if let Some(player) = state.interpolation.get(self.model.time) {
+                state.update(&player, delta_time);
+            }
+        }
+        let time = self.model.time;
+        match &mut self.spectator {
+            Some(spectator) => {
+                if let Some(player_id) = spectator.following {
+                    match self
+                        .players
+                        .get(&player_id)
+                        .and_then(|state| state.interpolation.get(time))
+                    {
+                        Some(player) => spectator.position = player.position + player.size / 2.0,
+                        None => spectator.following = None,
+                    }
+                }
+            }
+            None => {
+                self.players
+                    .entry(self.player.id)
+                    .or_default()
+                    .update(&self.player, delta_time);
+            }
+        }
         for item in self.model.items.values_mut() {
             let new_pos = vec2(item.position.x, item.position.y - delta_time * 5.0);
             if self
@@ -1197,13 +1966,86 @@ impl geng::State for GameState {
     }
     fn handle_event(&mut self, event: geng::Event) {
         self.ui_controller.handle_event(
-            &mut self.ui_state.ui(&self.model, self.connection.is_local()),
+            &mut self
+                .ui_state
+                .ui(&self.model, self.connection.is_local(), self.viewer()),
             event.clone(),
         );
+        if self.kicked.is_some() {
+            if let geng::Event::KeyDown {
+                key: geng::Key::Escape,
+                ..
+            } = event
+            {
+                self.transition = Some(geng::Transition::Pop);
+            }
+            return;
+        }
+        if self.spectator.is_some() {
+            self.handle_spectator_event(event);
+            return;
+        }
         if let geng::Event::KeyDown { key, .. } = event {
             let c = format!("{:?}", key);
+            if self.ui_state.chatting {
+                match key {
+                    geng::Key::Enter => {
+                        let text = mem::replace(&mut self.ui_state.chat_input, String::new());
+                        if !text.trim().is_empty() {
+                            self.to_send.push(ClientMessage::Chat(text));
+                        }
+                        self.ui_state.chatting = false;
+                    }
+                    geng::Key::Escape => {
+                        self.ui_state.chat_input.clear();
+                        self.ui_state.chatting = false;
+                    }
+                    geng::Key::Backspace => {
+                        self.ui_state.chat_input.pop();
+                    }
+                    geng::Key::Space => self.ui_state.chat_input.push(' '),
+                    _ if c.len() == 1 => {
+                        if self.ui_state.chat_input.len() < Model::MAX_CHAT_LENGTH {
+                            self.ui_state.chat_input.push_str(&c.to_lowercase());
+                        }
+                    }
+                    _ => {}
+                }
+                return;
+            }
+            if self.ui_state.choosing_team {
+                match key {
+                    geng::Key::Enter => {
+                        let name = mem::replace(&mut self.ui_state.team_input, String::new());
+                        self.to_send.push(if name.trim().is_empty() {
+                            ClientMessage::LeaveTeam
+                        } else {
+                            ClientMessage::Team(name)
+                        });
+                        self.ui_state.choosing_team = false;
+                    }
+                    geng::Key::Escape => {
+                        self.ui_state.team_input.clear();
+                        self.ui_state.choosing_team = false;
+                    }
+                    geng::Key::Backspace => {
+                        self.ui_state.team_input.pop();
+                    }
+                    _ if c.len() == 1 => {
+                        if self.ui_state.team_input.len() < Team::MAX_NAME_LENGTH {
+                            self.ui_state.team_input.push_str(&c);
+                        }
+                    }
+                    _ => {}
+                }
+                return;
+            }
+            if key == geng::Key::T && !self.ui_state.locked() {
+                self.ui_state.chatting = true;
+                return;
+            }
             if c.len() == 1 && self.ui_state.changing_name {
-                if self.player.name.len() < 20 {
+                if self.player.name.len() < Player::MAX_NAME_LENGTH {
                     self.player.name.push_str(&c);
                 }
                 return;
@@ -1214,6 +2056,12 @@ impl geng::State for GameState {
         }
         match event {
             geng::Event::KeyDown { key, .. } => match key {
+                geng::Key::Left | geng::Key::A if self.ui_state.leaderboard => {
+                    self.ui_state.leaderboard_tab = self.ui_state.leaderboard_tab.cycle(-1);
+                }
+                geng::Key::Right | geng::Key::D if self.ui_state.leaderboard => {
+                    self.ui_state.leaderboard_tab = self.ui_state.leaderboard_tab.cycle(1);
+                }
                 geng::Key::Escape | geng::Key::Enter | geng::Key::E if self.ui_state.locked() => {
                     self.ui_state.customize_character = false;
                     self.ui_state.changing_name = false;
@@ -1226,18 +2074,38 @@ impl geng::State for GameState {
                     });
                     if let Some(shop) = shop {
                         match shop.shop_type {
-                            ShopType::Train => {
-                                self.transition = Some(match self.connection {
-                                    Connection::Local { .. } => {
-                                        geng::Transition::Push(Box::new(ConnectingState::new(
-                                            &self.geng,
-                                            &self.assets,
-                                            &self.opt,
-                                            Some(self.player.clone()),
-                                        )))
+                            ShopType::Train(ref world) => {
+                                // The held item stays in the world being left,
+                                // the server drops it there once the player is gone
+                                let traveler = Player {
+                                    item: None,
+                                    ..self.player.clone()
+                                };
+                                let connect = |world| {
+                                    Box::new(ConnectingState::new(
+                                        &self.geng,
+                                        &self.assets,
+                                        &self.opt,
+                                        Some(traveler.clone()),
+                                        world,
+                                        false,
+                                    ))
+                                };
+                                let transition = match (&self.connection, world) {
+                                    (Connection::Local { .. }, None) => {
+                                        geng::Transition::Push(connect(self.opt.world.clone()))
+                                    }
+                                    (Connection::Local { .. }, Some(world)) => {
+                                        geng::Transition::Push(connect(Some(world.clone())))
                                     }
-                                    Connection::Remote(_) => geng::Transition::Pop,
-                                });
+                                    (Connection::Remote(_), Some(world)) => {
+                                        geng::Transition::Switch(connect(Some(world.clone())))
+                                    }
+                                    (Connection::Remote(_), None) | (Connection::Replay(_), _) => {
+                                        geng::Transition::Pop
+                                    }
+                                };
+                                self.transition = Some(transition);
                             }
                             ShopType::House => {
                                 self.ui_state.customize_character = true;
@@ -1245,6 +2113,9 @@ impl geng::State for GameState {
                             ShopType::Passport => {
                                 self.ui_state.changing_name = true;
                             }
+                            ShopType::Team => {
+                                self.ui_state.choosing_team = true;
+                            }
                             ShopType::LeaderBoard => {
                                 self.ui_state.leaderboard = true;
                             }
@@ -1276,6 +2147,7 @@ impl geng::State for GameState {
                                 }
                                 let item_id = item.id;
                                 self.player.item = None;
+                                self.to_send.push(ClientMessage::Sell);
                                 if let Some(item_type) = give_item {
                                     let mut effect = self.assets.change.effect();
                                     effect.set_volume(self.ui_state.volume);
@@ -1290,29 +2162,28 @@ impl geng::State for GameState {
                                     let mut effect = self.assets.money.effect();
                                     effect.set_volume(self.ui_state.volume);
                                     effect.play();
-                                    self.player.money += item.value;
+                                    self.player.money =
+                                        self.player.money.saturating_add(item.value);
                                 }
                             }
                         }
-                    } else {
+                    } else if !self.prediction.is_picking_up() {
                         let closest_item =
                             self.model.items.values().min_by_key(|item| {
                                 r32((item.position - self.player.position).len())
                             });
                         if let Some(item) = closest_item {
                             if (item.position - self.player.position).len() < Player::RANGE {
-                                self.player.item = Some(item.clone());
-                                self.to_send
-                                    .push(ClientMessage::Event(Event::ItemRemoved(item.id)));
+                                let item_id = item.id;
+                                let message = self.prediction.pick_up(&mut self.model, item_id);
+                                self.to_send.push(message);
                             }
                         }
                     }
                 }
                 geng::Key::Q => {
-                    if let Some(mut item) = self.player.item.take() {
-                        item.position = self.player.position;
-                        self.to_send
-                            .push(ClientMessage::Event(Event::ItemAdded(item)));
+                    if self.player.item.take().is_some() {
+                        self.to_send.push(ClientMessage::DropItem);
                     }
                 }
                 _ => {}
@@ -1333,7 +2204,7 @@ impl geng::State for GameState {
                 let position = position.map(|x| x.floor() as i32);
                 match button {
                     geng::MouseButton::Right => {
-                        if let Some(item) = &self.player.item {
+                        if let Some(item) = self.player.item.clone() {
                             if !self.model.tiles.contains_key(&position) {
                                 if let Some(tile) = item.item_type.placed() {
                                     if ((self.player.position + self.player.size / 2.0)
@@ -1341,9 +2212,15 @@ impl geng::State for GameState {
                                     .len()
                                         < Player::RANGE
                                     {
-                                        self.to_send.push(ClientMessage::Event(Event::TilePlaced(
-                                            position, tile,
-                                        )));
+                                        let mut effect = self.assets.place.effect();
+                                        effect.set_volume(self.ui_state.volume);
+                                        effect.play();
+                                        let message = self.prediction.predict(
+                                            &mut self.model,
+                                            Event::TilePlaced(position, tile),
+                                            Some(item),
+                                        );
+                                        self.to_send.push(message);
                                         self.player.item = None;
                                     }
                                 }
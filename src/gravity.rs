@@ -0,0 +1,105 @@
+use super::*;
+
+/// Patches of loose tiles, in different cells than the fluid pockets
+const LOOSE_PATCHES: Scatter = Scatter {
+    salt: 0x5EED_6A7E,
+    cell_size: 10,
+    chance: 30,
+    radius: 2.0..4.0,
+    stretch: 1.0,
+};
+const GRAVEL_MIN_DEPTH: i32 = 50;
+
+/// Loose tile of the patch the position is in, if any
+pub(crate) fn generate_loose(seed: u64, position: Vec2<i32>) -> Option<Tile> {
+    let (center, _) = LOOSE_PATCHES.find(seed, position, |_| true)?;
+    Some(if center.y < -GRAVEL_MIN_DEPTH {
+        Tile::Gravel
+    } else {
+        Tile::Sand
+    })
+}
+
+impl Model {
+    /// Seconds between falling steps
+    pub const GRAVITY_INTERVAL: f64 = 0.15;
+    pub const FALLING_TILE_DAMAGE: f32 = 25.0;
+    /// Widest gap a ceiling can span before caving in, when cave ins are on
+    pub const MAX_UNSUPPORTED_SPAN: i32 = 7;
+
+    /// Loose tiles at or above the position have to check whether they can fall again
+    pub(crate) fn wake_loose_tiles(&mut self, position: Vec2<i32>) {
+        for &delta in &[vec2(0, 0), vec2(0, 1)] {
+            let neighbour = position + delta;
+            if self.tiles.get(&neighbour).map_or(false, Tile::is_loose) {
+                self.loose_tiles.insert(neighbour);
+            }
+        }
+    }
+    /// Moves every unsupported loose tile one step down, returns the changed tiles.
+    /// Players hit by a falling tile get hurt.
+    pub(crate) fn drop_loose_tiles(
+        &mut self,
+        events: &mut Vec<Event>,
+    ) -> Vec<(Vec2<i32>, Option<Tile>)> {
+        let mut changes = Vec::new();
+        for position in bottom_up(mem::replace(&mut self.loose_tiles, default())) {
+            let tile = match self.tiles.get(&position) {
+                Some(&tile) if tile.is_loose() => tile,
+                _ => continue,
+            };
+            let below = position - vec2(0, 1);
+            if below.y < -WORLD_SIZE {
+                continue;
+            }
+            // Sinks through fluids, swapping places with them
+            let below_tile = self.tiles.get(&below).copied();
+            if below_tile.map_or(false, |tile| !tile.is_fluid()) {
+                continue;
+            }
+            self.set_tile(position, below_tile, &mut changes);
+            self.set_tile(below, Some(tile), &mut changes);
+            let mut hurt: Vec<Id> = self
+                .players
+                .values()
+                .filter(|player| player.body().tiles().any(|tile| tile == below))
+                .map(|player| player.id)
+                .collect();
+            // Not in map order, which can differ between copies of the model
+            hurt.sort_by_key(|id| id.raw());
+            for player_id in hurt {
+                events.push(Event::PlayerHurt(player_id, Self::FALLING_TILE_DAMAGE));
+            }
+        }
+        changes
+    }
+    /// Turns the ceiling above a too wide gap into falling gravel
+    pub(crate) fn cave_in(&mut self, position: Vec2<i32>) -> Vec<(Vec2<i32>, Option<Tile>)> {
+        let mut changes = Vec::new();
+        if !self.cave_ins || self.tiles.contains_key(&position) {
+            return changes;
+        }
+        let mut left = position.x;
+        while position.x - left <= Self::MAX_UNSUPPORTED_SPAN
+            && !self.tiles.contains_key(&vec2(left - 1, position.y))
+        {
+            left -= 1;
+        }
+        let mut right = position.x;
+        while right - position.x <= Self::MAX_UNSUPPORTED_SPAN
+            && !self.tiles.contains_key(&vec2(right + 1, position.y))
+        {
+            right += 1;
+        }
+        if right - left + 1 <= Self::MAX_UNSUPPORTED_SPAN {
+            return changes;
+        }
+        for x in left..=right {
+            let ceiling = vec2(x, position.y + 1);
+            if self.tiles.get(&ceiling).map_or(false, Tile::is_ground) {
+                self.set_tile(ceiling, Some(Tile::Gravel), &mut changes);
+            }
+        }
+        changes
+    }
+}
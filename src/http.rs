@@ -0,0 +1,73 @@
+use super::*;
+
+/// Body and content type of a response.
+pub struct Response {
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(value: &impl Serialize) -> Self {
+        Self {
+            content_type: "application/json",
+            body: serde_json::to_string_pretty(value).unwrap(),
+        }
+    }
+    pub fn text(body: String) -> Self {
+        Self {
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+}
+
+/// Serves GET requests on a background thread, the handler gets the path
+/// and returns None for paths that don't exist.
+pub fn spawn(
+    addr: impl std::net::ToSocketAddrs,
+    handler: impl Fn(&str) -> Option<Response> + Send + 'static,
+) -> std::io::Result<()> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &handler));
+            if let Err(e) = result {
+                warn!("Failed to answer an http request: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(
+    mut stream: std::net::TcpStream,
+    handler: &impl Fn(&str) -> Option<Response>,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    std::io::BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut words = request_line.split_whitespace();
+    let (status, response) = match (words.next(), words.next()) {
+        (Some("GET"), Some(target)) => {
+            // Query strings are not used by any route
+            let path = target.split('?').next().unwrap();
+            match handler(path) {
+                Some(response) => ("200 OK", response),
+                None => ("404 Not Found", Response::text("Not found\n".to_owned())),
+            }
+        }
+        _ => (
+            "405 Method Not Allowed",
+            Response::text("Only GET is supported\n".to_owned()),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.content_type,
+        response.body.len(),
+        response.body,
+    )?;
+    stream.flush()
+}
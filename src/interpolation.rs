@@ -0,0 +1,152 @@
+use super::*;
+
+/// Reconstructs a smooth state of a remote player from the snapshots received from the server.
+///
+/// Snapshots are stamped with the server's time and rendered with a small delay,
+/// so there is usually a pair to interpolate between.
+/// When updates stop arriving the last known movement is extrapolated for a limited time,
+/// and any jump caused by late data is hidden by an error offset that decays over time.
+pub struct PlayerInterpolation {
+    snapshots: std::collections::VecDeque<(f64, Player)>,
+    error: Vec2<f32>,
+}
+
+impl PlayerInterpolation {
+    pub const DELAY: f64 = 0.15;
+    pub const MAX_EXTRAPOLATION: f64 = 0.25;
+    pub const MAX_SNAPSHOTS: usize = 32;
+    pub const ERROR_CORRECTION_SPEED: f32 = 10.0;
+    pub const TELEPORT_DISTANCE: f32 = 3.0;
+
+    pub fn new() -> Self {
+        Self {
+            snapshots: default(),
+            error: vec2(0.0, 0.0),
+        }
+    }
+    pub fn push(&mut self, time: f64, player: Player) {
+        if let Some((last_time, _)) = self.snapshots.back() {
+            if time < *last_time {
+                return;
+            }
+            // Several updates arrived within one server tick, the newest one wins
+            if time == *last_time {
+                self.snapshots.pop_back();
+            }
+        }
+        let before = self.sample(time).map(|player| player.position);
+        self.snapshots.push_back((time, player));
+        while self.snapshots.len() > Self::MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        if let (Some(before), Some(after)) = (before, self.sample(time)) {
+            self.error += before - after.position;
+            if self.error.len() > Self::TELEPORT_DISTANCE {
+                self.error = vec2(0.0, 0.0);
+            }
+        }
+    }
+    pub fn update(&mut self, time: f64, delta_time: f32) {
+        let render_time = time - Self::DELAY;
+        while self.snapshots.len() > 2 && self.snapshots[1].0 <= render_time {
+            self.snapshots.pop_front();
+        }
+        self.error = self.error * (-Self::ERROR_CORRECTION_SPEED * delta_time).exp();
+    }
+    pub fn get(&self, time: f64) -> Option<Player> {
+        let mut player = self.sample(time)?;
+        player.position += self.error;
+        Some(player)
+    }
+    fn sample(&self, time: f64) -> Option<Player> {
+        let render_time = time - Self::DELAY;
+        let (first_time, first) = self.snapshots.front()?;
+        if render_time <= *first_time {
+            return Some(first.clone());
+        }
+        for ((prev_time, prev), (next_time, next)) in
+            self.snapshots.iter().zip(self.snapshots.iter().skip(1))
+        {
+            if render_time < *next_time {
+                let t = ((render_time - prev_time) / (next_time - prev_time)) as f32;
+                return Some(Self::lerp(prev, next, t));
+            }
+        }
+        let (last_time, last) = self.snapshots.back().unwrap();
+        let mut player = last.clone();
+        if self.snapshots.len() >= 2 {
+            let (prev_time, prev) = &self.snapshots[self.snapshots.len() - 2];
+            if last_time > prev_time {
+                let velocity = (last.position - prev.position) / (last_time - prev_time) as f32;
+                let extrapolation = (render_time - last_time).min(Self::MAX_EXTRAPOLATION);
+                player.position += velocity * extrapolation as f32;
+            }
+        }
+        Some(player)
+    }
+    fn lerp(prev: &Player, next: &Player, t: f32) -> Player {
+        let mut player = next.clone();
+        player.position = prev.position * (1.0 - t) + next.position * t;
+        if let (Some(prev_swing), Some(next_swing)) = (prev.swing, next.swing) {
+            if next_swing >= prev_swing {
+                player.swing = Some(prev_swing * (1.0 - t) + next_swing * t);
+            }
+        }
+        player
+    }
+}
+
+impl Default for PlayerInterpolation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_at(x: f32) -> Player {
+        let mut player = Player::new(&mut IdGen::new(), &mut global_rng());
+        player.position = vec2(x, 0.0);
+        player
+    }
+
+    #[test]
+    fn interpolates_between_snapshots() {
+        let mut interpolation = PlayerInterpolation::new();
+        interpolation.push(0.0, player_at(0.0));
+        interpolation.push(1.0, player_at(10.0));
+        let player = interpolation.get(0.5 + PlayerInterpolation::DELAY).unwrap();
+        assert!((player.position.x - 5.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn extrapolates_for_a_limited_time() {
+        let mut interpolation = PlayerInterpolation::new();
+        interpolation.push(0.0, player_at(0.0));
+        interpolation.push(0.1, player_at(1.0));
+        let player = interpolation.get(10.0).unwrap();
+        let expected = 1.0 + 10.0 * PlayerInterpolation::MAX_EXTRAPOLATION as f32;
+        assert!((player.position.x - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn keeps_the_newest_snapshot_of_a_tick() {
+        let mut interpolation = PlayerInterpolation::new();
+        interpolation.push(0.0, player_at(0.0));
+        interpolation.push(1.0, player_at(5.0));
+        interpolation.push(1.0, player_at(10.0));
+        let player = interpolation.get(0.5 + PlayerInterpolation::DELAY).unwrap();
+        assert!((player.position.x - 5.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ignores_late_snapshots() {
+        let mut interpolation = PlayerInterpolation::new();
+        interpolation.push(1.0, player_at(1.0));
+        interpolation.push(0.5, player_at(100.0));
+        let player = interpolation.get(10.0).unwrap();
+        assert_eq!(player.position.x, 1.0);
+    }
+}
@@ -0,0 +1,31 @@
+use batbox::*;
+
+pub mod biome;
+#[cfg(feature = "net")]
+pub mod bot;
+pub mod creature;
+pub mod fluid;
+pub mod gravity;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod http;
+pub mod interpolation;
+pub mod light;
+pub mod model;
+pub mod net;
+pub mod prediction;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rate_limit;
+pub mod recording;
+#[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+pub mod server;
+
+pub use biome::*;
+pub use creature::*;
+pub use interpolation::*;
+pub use light::*;
+pub use model::*;
+pub use net::*;
+pub use prediction::*;
+pub use recording::*;
+#[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+pub use server::{LeaderboardEntry, Server, WorldSettings, WorldStatus};
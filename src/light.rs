@@ -0,0 +1,189 @@
+use super::*;
+
+/// Light level of every tile underground, from sunlight shining down from the surface
+/// and from glowing tiles. Only kept by clients, for rendering.
+#[derive(Debug, Clone, Default)]
+pub struct LightMap {
+    levels: HashMap<Vec2<i32>, u8>,
+}
+
+impl Tile {
+    /// Light level given off by the tile itself
+    pub fn light(&self) -> u8 {
+        match self {
+            Self::Torch => LightMap::MAX_LEVEL - 1,
+            Self::Lava => LightMap::MAX_LEVEL - 3,
+            _ => 0,
+        }
+    }
+}
+
+impl LightMap {
+    /// Light level of the sunlight, goes down by one every tile it spreads
+    pub const MAX_LEVEL: u8 = 15;
+    const RADIUS: i32 = Self::MAX_LEVEL as i32;
+
+    pub fn new(tiles: &TileMap) -> Self {
+        let mut map = Self::default();
+        map.recompute(tiles, vec2(-WORLD_SIZE, -WORLD_SIZE), vec2(WORLD_SIZE, -1));
+        map
+    }
+    /// Light level between 0 and 1
+    pub fn get(&self, position: Vec2<i32>) -> f32 {
+        let level = if position.y >= 0 {
+            Self::MAX_LEVEL
+        } else {
+            self.levels.get(&position).copied().unwrap_or(0)
+        };
+        level as f32 / Self::MAX_LEVEL as f32
+    }
+    /// Recomputes the light around the tile after it changed
+    pub fn update(&mut self, tiles: &TileMap, position: Vec2<i32>) {
+        // Opening or closing a sunlit column changes the light all the way down
+        let bottom = if Self::sunlit(tiles, position) {
+            -WORLD_SIZE
+        } else {
+            position.y - Self::RADIUS
+        };
+        self.recompute(
+            tiles,
+            vec2(position.x - Self::RADIUS, bottom),
+            vec2(position.x + Self::RADIUS, position.y + Self::RADIUS),
+        );
+    }
+    fn lets_light_through(tiles: &TileMap, position: Vec2<i32>) -> bool {
+        tiles.get(&position).map_or(true, Tile::transparent)
+    }
+    fn sunlit(tiles: &TileMap, position: Vec2<i32>) -> bool {
+        (position.y + 1..0).all(|y| Self::lets_light_through(tiles, vec2(position.x, y)))
+    }
+    /// Recomputes the light levels in the rectangle, looking at light sources
+    /// close enough around it to reach inside
+    fn recompute(&mut self, tiles: &TileMap, min: Vec2<i32>, max: Vec2<i32>) {
+        let min = vec2(min.x.max(-WORLD_SIZE - 1), min.y.max(-WORLD_SIZE));
+        let max = vec2(max.x.min(WORLD_SIZE + 1), max.y.min(-1));
+        if min.x > max.x || min.y > max.y {
+            return;
+        }
+        let area_min = vec2(
+            min.x - Self::RADIUS,
+            (min.y - Self::RADIUS).max(-WORLD_SIZE),
+        );
+        let area_max = vec2(max.x + Self::RADIUS, (max.y + Self::RADIUS).min(-1));
+        let in_area = |position: Vec2<i32>| {
+            position.x >= area_min.x
+                && position.x <= area_max.x
+                && position.y >= area_min.y
+                && position.y <= area_max.y
+        };
+
+        let mut levels = HashMap::new();
+        let mut queues = vec![Vec::new(); Self::MAX_LEVEL as usize + 1];
+        for x in area_min.x..=area_max.x {
+            let mut sunlit = Self::sunlit(tiles, vec2(x, area_max.y));
+            for y in (area_min.y..=area_max.y).rev() {
+                let position = vec2(x, y);
+                let mut level = tiles.get(&position).map_or(0, Tile::light);
+                if sunlit {
+                    level = Self::MAX_LEVEL;
+                    sunlit = Self::lets_light_through(tiles, position);
+                }
+                if level > 0 {
+                    levels.insert(position, level);
+                    queues[level as usize].push(position);
+                }
+            }
+        }
+        // Brightest first, so every tile is reached by its brightest source first
+        for level in (2..=Self::MAX_LEVEL).rev() {
+            for position in mem::take(&mut queues[level as usize]) {
+                if levels[&position] != level || !Self::lets_light_through(tiles, position) {
+                    continue;
+                }
+                for &delta in &[vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1)] {
+                    let neighbour = position + delta;
+                    if !in_area(neighbour) {
+                        continue;
+                    }
+                    let current = levels.entry(neighbour).or_insert(0);
+                    if *current < level - 1 {
+                        *current = level - 1;
+                        queues[level as usize - 1].push(neighbour);
+                    }
+                }
+            }
+        }
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let position = vec2(x, y);
+                match levels.get(&position) {
+                    Some(&level) if level > 0 => {
+                        self.levels.insert(position, level);
+                    }
+                    _ => {
+                        self.levels.remove(&position);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid() -> TileMap {
+        let mut tiles = TileMap::new();
+        for x in -30..=30 {
+            for y in -40..0 {
+                tiles.insert(vec2(x, y), Tile::Stone);
+            }
+        }
+        tiles
+    }
+
+    #[test]
+    fn torch_lights_the_walls_around_it() {
+        let mut tiles = solid();
+        tiles.insert(vec2(0, -20), Tile::Torch);
+        let mut map = LightMap::default();
+        map.recompute(&tiles, vec2(-10, -30), vec2(10, -1));
+        assert_eq!(map.levels.get(&vec2(0, -20)), Some(&14));
+        assert_eq!(map.levels.get(&vec2(1, -20)), Some(&13));
+        assert_eq!(map.get(vec2(2, -20)), 0.0);
+    }
+
+    #[test]
+    fn sunlight_shines_down_open_shafts() {
+        let mut tiles = solid();
+        for y in -10..0 {
+            tiles.remove(&vec2(5, y));
+        }
+        let mut map = LightMap::default();
+        map.recompute(&tiles, vec2(-10, -30), vec2(10, -1));
+        assert_eq!(map.get(vec2(5, -10)), 1.0);
+        assert_eq!(map.levels.get(&vec2(5, -11)), Some(&LightMap::MAX_LEVEL));
+        assert_eq!(
+            map.levels.get(&vec2(4, -10)),
+            Some(&(LightMap::MAX_LEVEL - 1))
+        );
+        assert_eq!(map.get(vec2(5, -12)), 0.0);
+    }
+
+    #[test]
+    fn update_matches_recomputing_everything() {
+        let mut tiles = solid();
+        let mut map = LightMap::new(&tiles);
+        for y in -15..0 {
+            tiles.remove(&vec2(0, y));
+            map.update(&tiles, vec2(0, y));
+        }
+        tiles.insert(vec2(0, -15), Tile::Torch);
+        map.update(&tiles, vec2(0, -15));
+        tiles.insert(vec2(0, -5), Tile::Stone);
+        map.update(&tiles, vec2(0, -5));
+        assert_eq!(map.levels, LightMap::new(&tiles).levels);
+    }
+}
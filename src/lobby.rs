@@ -28,7 +28,7 @@ impl geng::State for Lobby {
             geng::Event::KeyDown { key, .. } => {
                 match key {
                     geng::Key::Num1 => {
-                        let mut model = Model::new();
+                        let mut model = Model::new(self.opt.seed());
                         let (welcome, _) = model.welcome();
                         self.transition = Some(geng::Transition::Push(Box::new(GameState::new(
                             &self.geng,
@@ -44,7 +44,14 @@ impl geng::State for Lobby {
                     }
                     geng::Key::Num2 => {
                         self.transition = Some(geng::Transition::Push(Box::new(
-                            ConnectingState::new(&self.geng, &self.assets, &self.opt, None),
+                            ConnectingState::new(
+                                &self.geng,
+                                &self.assets,
+                                &self.opt,
+                                None,
+                                self.opt.world.clone(),
+                                false,
+                            ),
                         )));
                     }
                     _ => {}
@@ -73,11 +80,17 @@ impl ConnectingState {
         assets: &Rc<Assets>,
         opt: &Rc<Opt>,
         player: Option<Player>,
+        world: Option<String>,
+        spectate: bool,
     ) -> Self {
-        let addr = format!("{}://{}", option_env!("WSS").unwrap_or("ws"), opt.addr());
         let connection = Box::pin(
-            geng::net::client::connect(&addr)
-                .then(|connection| async move {
+            geng::net::client::connect(&opt.connection_addr())
+                .then(move |mut connection| async move {
+                    connection.send(if spectate {
+                        ClientMessage::Spectate(world)
+                    } else {
+                        ClientMessage::Join(world, Identity::load())
+                    });
                     let (message, connection) = connection.into_future().await;
                     let welcome = match message {
                         Some(ServerMessage::Welcome(message)) => message,
@@ -1,22 +1,17 @@
 use geng::prelude::*;
+use ludumdare48::*;
 
 pub mod camera;
 pub mod game_state;
 pub mod lobby;
-pub mod model;
-pub mod net;
 pub mod renderer;
-#[cfg(not(target_arch = "wasm32"))]
-pub mod server;
+pub mod spectator;
 
 pub use camera::*;
 pub use game_state::GameState;
 pub use lobby::*;
-pub use model::*;
-pub use net::*;
 pub use renderer::*;
-#[cfg(not(target_arch = "wasm32"))]
-pub use server::Server;
+pub use spectator::*;
 
 pub fn hsv(h: f32, s: f32, v: f32) -> Color<f32> {
     hsva(h, s, v, 1.0)
@@ -88,6 +83,14 @@ pub struct Assets {
     pub dirt: Vec<ugli::Texture>,
     #[asset(path = "ladder/*.png", range = "1..=2")]
     pub ladder: Vec<ugli::Texture>,
+    #[asset(path = "clay/*.png", range = "1..=3")]
+    pub clay: Vec<ugli::Texture>,
+    #[asset(path = "granite/*.png", range = "1..=3")]
+    pub granite: Vec<ugli::Texture>,
+    #[asset(path = "crystal/*.png", range = "1..=3")]
+    pub crystal: Vec<ugli::Texture>,
+    #[asset(path = "basalt/*.png", range = "1..=3")]
+    pub basalt: Vec<ugli::Texture>,
     pub stick: ugli::Texture,
     pub pick_head: ugli::Texture,
     pub body: ugli::Texture,
@@ -143,6 +146,7 @@ impl Assets {
             ItemType::Block => &self.block_item,
             ItemType::Chest => &self.chest,
             ItemType::Ladder => &self.ladder_item,
+            ItemType::Torch => &self.stick,
         }
     }
     pub fn tile_textures(&self, tile: Tile) -> &[ugli::Texture] {
@@ -151,6 +155,16 @@ impl Assets {
             Tile::Ladder => &self.ladder,
             Tile::Block => &self.block,
             Tile::Dirt => &self.dirt,
+            // Fluids are drawn as tinted blocks
+            Tile::Water | Tile::Lava => &self.block,
+            // Loose tiles are drawn as tinted ground
+            Tile::Sand => &self.dirt,
+            Tile::Gravel => &self.stone,
+            Tile::Torch => std::slice::from_ref(&self.stick),
+            Tile::Clay => &self.clay,
+            Tile::Granite => &self.granite,
+            Tile::Crystal => &self.crystal,
+            Tile::Basalt => &self.basalt,
         }
     }
 }
@@ -163,6 +177,48 @@ pub struct Opt {
     server: bool,
     #[structopt(long)]
     with_server: bool,
+    /// Seed for the world, random by default
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// JSON file with a list of worlds to host, see WorldSettings
+    #[structopt(long)]
+    worlds: Option<String>,
+    /// File to load the world from and save it to, when not using --worlds
+    #[structopt(long)]
+    save: Option<String>,
+    /// World to join on the server, the first one by default
+    #[structopt(long)]
+    world: Option<String>,
+    /// Play competitive rounds of this many seconds, when not using --worlds
+    #[structopt(long)]
+    round_duration: Option<f64>,
+    /// Directory to keep results of finished rounds in, when not using --worlds
+    #[structopt(long)]
+    round_archive: Option<String>,
+    /// Collapse wide unsupported ceilings, when not using --worlds
+    #[structopt(long)]
+    cave_ins: bool,
+    /// Record all server events into this file
+    #[structopt(long)]
+    record: Option<String>,
+    /// Serve server status over HTTP on this address, like 127.0.0.1:1156
+    #[structopt(long)]
+    status: Option<String>,
+    /// Export the leaderboards to this JSON file every few seconds
+    #[structopt(long)]
+    leaderboard: Option<String>,
+    /// Watch the server without spawning a player
+    #[structopt(long)]
+    spectate: bool,
+    /// Watch a recording made with --record
+    #[structopt(long)]
+    replay: Option<String>,
+    /// Run this many headless bots instead of the game
+    #[structopt(long)]
+    bots: Option<usize>,
+    /// One of wander, dig, ladders or sell. Bots cycle through all of them by default
+    #[structopt(long)]
+    bot_behaviour: Option<bot::BotBehaviour>,
 }
 
 impl Opt {
@@ -172,6 +228,41 @@ impl Opt {
             None => option_env!("SERVER_ADDR").unwrap_or("127.0.0.1:1155"),
         }
     }
+    pub fn seed(&self) -> u64 {
+        self.seed.unwrap_or_else(|| global_rng().gen())
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn create_server(&self) -> Server {
+        let worlds = match &self.worlds {
+            Some(path) => serde_json::from_reader(std::io::BufReader::new(
+                std::fs::File::open(path).expect("Failed to open worlds file"),
+            ))
+            .expect("Failed to parse worlds file"),
+            None => vec![WorldSettings {
+                save: self.save.clone(),
+                round_duration: self.round_duration,
+                round_archive: self.round_archive.clone(),
+                cave_ins: self.cave_ins,
+                ..WorldSettings::new("main", self.seed())
+            }],
+        };
+        let server = Server::new(self.addr(), worlds);
+        if let Some(path) = &self.record {
+            server.record(path).expect("Failed to start recording");
+        }
+        if let Some(path) = &self.leaderboard {
+            server.spawn_leaderboard_export(path);
+        }
+        if let Some(addr) = &self.status {
+            server
+                .spawn_status(addr.as_str())
+                .expect("Failed to start the status endpoint");
+        }
+        server
+    }
+    pub fn connection_addr(&self) -> String {
+        format!("{}://{}", option_env!("WSS").unwrap_or("ws"), self.addr())
+    }
 }
 
 fn main() {
@@ -191,11 +282,18 @@ fn main() {
     let opt = Rc::new(opt);
     if opt.server {
         #[cfg(not(target_arch = "wasm32"))]
-        Server::new(opt.addr(), Model::new()).run();
+        {
+            let server = opt.create_server();
+            server.spawn_console();
+            server.run();
+        }
+    } else if let Some(bots) = opt.bots {
+        #[cfg(not(target_arch = "wasm32"))]
+        bot::run(&opt.connection_addr(), bots, opt.bot_behaviour);
     } else {
         #[cfg(not(target_arch = "wasm32"))]
         let server = if opt.with_server {
-            let server = Server::new(opt.addr(), Model::new());
+            let server = opt.create_server();
             let server_handle = server.handle();
             let server_thread = std::thread::spawn(move || {
                 server.run();
@@ -216,9 +314,26 @@ fn main() {
                 move |assets| {
                     let mut assets = assets.unwrap();
                     assets.music.looped = true;
-                    let mut model = Model::new();
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if let Some(path) = &opt.replay {
+                        let recording =
+                            Recording::load(path).expect("Failed to load the recording");
+                        return Box::new(GameState::replay(&geng, &Rc::new(assets), &opt, recording))
+                            as Box<dyn geng::State>;
+                    }
+                    if opt.spectate {
+                        return Box::new(ConnectingState::new(
+                            &geng,
+                            &Rc::new(assets),
+                            &opt,
+                            None,
+                            opt.world.clone(),
+                            true,
+                        )) as Box<dyn geng::State>;
+                    }
+                    let mut model = Model::new(opt.seed());
                     let (welcome, _) = model.welcome();
-                    GameState::new(
+                    Box::new(GameState::new(
                         &geng,
                         &Rc::new(assets),
                         &opt,
@@ -228,7 +343,7 @@ fn main() {
                             next_tick: 0.0,
                             model,
                         },
-                    )
+                    ))
                     // Lobby::new(&geng, Rc::new(assets), &opt)
                 }
             }),
@@ -25,6 +25,42 @@ impl IdGen {
     }
 }
 
+/// SplitMix64 generator that is serialized together with the model,
+/// so the same seed and events always give the same world and loot
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelRng {
+    state: u64,
+}
+
+impl ModelRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl rand::RngCore for ModelRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Player {
     pub id: Id,
@@ -48,6 +84,33 @@ pub struct Player {
     pub mustache: usize,
     pub nose: usize,
     pub name: String,
+    pub health: f32,
+    /// How long the player has been falling for
+    pub fall_time: f32,
+}
+
+/// Counters shown on the leaderboards, kept by the server.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PlayerStats {
+    /// Deepest tile reached below the surface
+    pub max_depth: f32,
+    pub tiles_broken: usize,
+    pub chests_found: usize,
+    pub blocks_placed: usize,
+    /// Seconds, not counting the current session
+    pub time_played: usize,
+    /// Model time the current session started at, None when not playing
+    pub joined_at: Option<f64>,
+}
+
+impl PlayerStats {
+    /// Seconds played up to `now`, the current session included
+    pub fn total_time_played(&self, now: f64) -> usize {
+        let session = self
+            .joined_at
+            .map_or(0.0, |joined_at| (now - joined_at).max(0.0));
+        self.time_played + session as usize
+    }
 }
 
 impl Player {
@@ -56,7 +119,19 @@ impl Player {
     pub const JUMP_SPEED: f32 = 4.0;
     pub const JUMP_TIME: f32 = 0.3;
     pub const SWING_SPEED: f32 = 2.0;
-    pub fn new(id_gen: &mut IdGen) -> Self {
+    pub const MAX_NAME_LENGTH: usize = 20;
+    /// Extra distance allowed by the server when checking pick ups, since positions lag behind
+    pub const PICK_UP_TOLERANCE: f32 = 1.0;
+    pub const MAX_HEALTH: f32 = 100.0;
+    /// Falls shorter than this many seconds don't hurt
+    pub const SAFE_FALL_TIME: f32 = 0.6;
+    /// Damage per second of falling after the safe time
+    pub const FALL_DAMAGE: f32 = 80.0;
+    /// Speed multiplier when in water
+    pub const WATER_SLOWDOWN: f32 = 0.5;
+    /// Damage per second when in lava
+    pub const LAVA_DAMAGE: f32 = 40.0;
+    pub fn new(id_gen: &mut IdGen, rng: &mut impl rand::Rng) -> Self {
         Self {
             id: id_gen.gen(),
             position: vec2(0.0, 0.0),
@@ -68,17 +143,126 @@ impl Player {
             swing: None,
             item: None,
             money: 0,
-            skin_tone: global_rng().gen_range(0.0..1.0),
-            stick: global_rng().gen_range(0.0..1.0),
-            hat_color: global_rng().gen_range(0.0..1.0),
-            beard: global_rng().gen_range(0..5),
-            ear: global_rng().gen_range(0..4),
-            eye: global_rng().gen_range(0..4),
-            hat: global_rng().gen_range(0..5),
-            mouth: global_rng().gen_range(0..4),
-            mustache: global_rng().gen_range(0..5),
-            nose: global_rng().gen_range(0..4),
+            skin_tone: rng.gen_range(0.0..1.0),
+            stick: rng.gen_range(0.0..1.0),
+            hat_color: rng.gen_range(0.0..1.0),
+            beard: rng.gen_range(0..5),
+            ear: rng.gen_range(0..4),
+            eye: rng.gen_range(0..4),
+            hat: rng.gen_range(0..5),
+            mouth: rng.gen_range(0..4),
+            mustache: rng.gen_range(0..5),
+            nose: rng.gen_range(0..4),
             name: String::new(),
+            health: Self::MAX_HEALTH,
+            fall_time: 0.0,
+        }
+    }
+    pub fn damage(&mut self, amount: f32) {
+        self.health = (self.health - amount).max(0.0);
+    }
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+    /// The player as its client sent it, with the item, money and health the server gave it
+    fn updated_by_client(&self, update: Player) -> Self {
+        Self {
+            id: self.id,
+            item: self.item.clone(),
+            money: self.money,
+            health: self.health,
+            ..update
+        }
+    }
+    /// Brings the player back at the surface with full health, the held item is dropped separately
+    pub fn respawn(&mut self) {
+        self.position = vec2(0.0, 0.0);
+        self.target_velocity = vec2(0.0, 0.0);
+        self.jump_timer = 0.0;
+        self.swing = None;
+        self.health = Self::MAX_HEALTH;
+        self.fall_time = 0.0;
+    }
+    pub fn body(&self) -> Body {
+        Body {
+            position: self.position,
+            target_velocity: self.target_velocity,
+            size: self.size,
+            jump_timer: self.jump_timer,
+            on_ground: self.on_ground,
+            looks_right: self.looks_right,
+        }
+    }
+    fn set_body(&mut self, body: Body) {
+        self.position = body.position;
+        self.target_velocity = body.target_velocity;
+        self.size = body.size;
+        self.jump_timer = body.jump_timer;
+        self.on_ground = body.on_ground;
+        self.looks_right = body.looks_right;
+    }
+    pub fn matrix(&self) -> Mat4<f32> {
+        self.body().matrix()
+    }
+    pub fn update(&mut self, tiles: &TileMap, delta_time: f32) {
+        let initial_position = self.position;
+        let mut body = self.body();
+        let touching: Vec<Tile> = body
+            .tiles()
+            .filter_map(|position| tiles.get(&position).copied())
+            .collect();
+        let in_water = touching.contains(&Tile::Water);
+        body.update(
+            tiles,
+            if in_water {
+                Self::SPEED * Self::WATER_SLOWDOWN
+            } else {
+                Self::SPEED
+            },
+            delta_time,
+        );
+        self.set_body(body);
+        if touching.contains(&Tile::Lava) {
+            self.damage(Self::LAVA_DAMAGE * delta_time);
+        }
+        // Climbing down ladders is not falling, and water breaks the fall
+        if in_water {
+            self.fall_time = 0.0;
+        } else if self.position.y < initial_position.y && !self.on_ground {
+            self.fall_time += delta_time;
+        } else {
+            // Landing hurts more the longer the fall was
+            let fall_time = mem::replace(&mut self.fall_time, 0.0);
+            if fall_time > Self::SAFE_FALL_TIME {
+                self.damage((fall_time - Self::SAFE_FALL_TIME) * Self::FALL_DAMAGE);
+            }
+        }
+        if let Some(swing) = &mut self.swing {
+            *swing += delta_time * Self::SWING_SPEED;
+        }
+    }
+}
+
+/// Walking, jumping and climbing through tiles, shared by players and creatures.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Body {
+    pub position: Vec2<f32>,
+    pub target_velocity: Vec2<f32>,
+    pub size: Vec2<f32>,
+    pub jump_timer: f32,
+    pub on_ground: bool,
+    pub looks_right: bool,
+}
+
+impl Body {
+    pub fn new(position: Vec2<f32>, size: Vec2<f32>) -> Self {
+        Self {
+            position,
+            target_velocity: vec2(0.0, 0.0),
+            size,
+            jump_timer: 0.0,
+            on_ground: false,
+            looks_right: true,
         }
     }
     pub fn matrix(&self) -> Mat4<f32> {
@@ -89,6 +273,9 @@ impl Player {
         }
         matrix
     }
+    pub fn center(&self) -> Vec2<f32> {
+        self.position + self.size / 2.0
+    }
     pub fn tiles(&self) -> impl Iterator<Item = Vec2<i32>> + '_ {
         (self.position.x.floor() as i32..(self.position.x + self.size.x).ceil() as i32).flat_map(
             move |x| {
@@ -97,17 +284,18 @@ impl Player {
             },
         )
     }
-    pub fn update(&mut self, tiles: &TileMap, delta_time: f32) {
+    /// Moves in the direction of `target_velocity`, horizontally at `speed`
+    pub fn update(&mut self, tiles: &TileMap, speed: f32, delta_time: f32) {
         let initial_position = self.position;
         let mut velocity = self.target_velocity;
-        velocity.x *= Self::SPEED;
+        velocity.x *= speed;
         if velocity.y != 1.0 {
             self.jump_timer = 0.0;
         }
         if self.jump_timer <= 0.0 {
             velocity.y = -1.0;
         }
-        velocity.y *= Self::JUMP_SPEED;
+        velocity.y *= Player::JUMP_SPEED;
         self.jump_timer -= delta_time;
         let delta_position = velocity * delta_time;
         self.position.x += delta_position.x;
@@ -124,7 +312,7 @@ impl Player {
         ) || on_platform && self.target_velocity.y >= 0.0
         {
             if self.position.y < initial_position.y {
-                self.jump_timer = Self::JUMP_TIME;
+                self.jump_timer = Player::JUMP_TIME;
                 self.on_ground = true;
             } else {
                 self.jump_timer = 0.0;
@@ -140,9 +328,6 @@ impl Player {
         if self.position.x > initial_position.x {
             self.looks_right = true;
         }
-        if let Some(swing) = &mut self.swing {
-            *swing += delta_time * Self::SWING_SPEED;
-        }
     }
     fn collide(
         &self,
@@ -153,11 +338,9 @@ impl Player {
         for position in self.tiles() {
             if let Some(tile) = tiles.get(&position) {
                 if !tile.can_move_through()
-                    && !AABB::pos_size(
-                        position.map(|x| x as f32) - self.size,
-                        vec2(1.0, 1.0) + self.size,
-                    )
-                    .contains(initial_position)
+                    && !AABB::point(position.map(|x| x as f32) - self.size)
+                        .extend_positive(vec2(1.0, 1.0) + self.size)
+                        .contains(initial_position)
                 {
                     return true;
                 }
@@ -176,6 +359,17 @@ pub enum Tile {
     Dirt,
     Ladder,
     Block,
+    Water,
+    Lava,
+    /// Falls when nothing is below it
+    Sand,
+    Gravel,
+    /// Lights up the caves around it
+    Torch,
+    Clay,
+    Granite,
+    Crystal,
+    Basalt,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Copy)]
@@ -183,6 +377,7 @@ pub enum ItemType {
     Block,
     Ladder,
     Chest,
+    Torch,
 }
 
 impl ItemType {
@@ -191,14 +386,7 @@ impl ItemType {
             Self::Block => Color::rgb(0.8, 0.8, 0.8),
             Self::Ladder => Color::WHITE,
             Self::Chest => Color::rgb(0.7, 0.3, 0.0),
-        }
-    }
-    pub fn random() -> Self {
-        match global_rng().gen_range(0..3) {
-            0 => Self::Block,
-            1 => Self::Ladder,
-            2 => Self::Chest,
-            _ => unreachable!(),
+            Self::Torch => Color::rgb(1.0, 0.6, 0.2),
         }
     }
     pub fn placed(&self) -> Option<Tile> {
@@ -206,6 +394,7 @@ impl ItemType {
             Self::Block => Some(Tile::Block),
             Self::Chest => None,
             Self::Ladder => Some(Tile::Ladder),
+            Self::Torch => Some(Tile::Torch),
         }
     }
 }
@@ -234,28 +423,71 @@ impl Tile {
     pub fn can_move_through(&self) -> bool {
         match self {
             Self::Stone | Self::Dirt => false,
-            Self::Ladder => true,
+            Self::Clay | Self::Granite | Self::Crystal | Self::Basalt => false,
+            Self::Ladder | Self::Torch => true,
             Self::Block => false,
+            Self::Sand | Self::Gravel => false,
+            Self::Water | Self::Lava => true,
         }
     }
+    /// Water can be swum up like a ladder
     pub fn can_climb(&self) -> bool {
         match self {
-            Self::Ladder => true,
+            Self::Ladder | Self::Water => true,
             _ => false,
         }
     }
     pub fn transparent(&self) -> bool {
         match self {
-            Self::Ladder => true,
+            Self::Ladder | Self::Torch => true,
             Self::Stone | Self::Dirt => false,
+            Self::Clay | Self::Granite | Self::Crystal | Self::Basalt => false,
             Self::Block => false,
+            Self::Sand | Self::Gravel => false,
+            Self::Water | Self::Lava => true,
         }
     }
     pub fn need_border(&self) -> bool {
         match self {
             Self::Stone | Self::Dirt => true,
-            Self::Ladder => false,
+            Self::Clay | Self::Granite | Self::Crystal | Self::Basalt => true,
+            Self::Ladder | Self::Torch => false,
             Self::Block => true,
+            Self::Sand | Self::Gravel => true,
+            Self::Water | Self::Lava => false,
+        }
+    }
+    /// Flows in Model::tick and can't be dug
+    pub fn is_fluid(&self) -> bool {
+        match self {
+            Self::Water | Self::Lava => true,
+            _ => false,
+        }
+    }
+    /// Falls in Model::tick when unsupported
+    pub fn is_loose(&self) -> bool {
+        match self {
+            Self::Sand | Self::Gravel => true,
+            _ => false,
+        }
+    }
+    /// Generated as part of a layer, drops the layer's loot when broken
+    pub fn is_ground(&self) -> bool {
+        match self {
+            Self::Stone | Self::Dirt => true,
+            Self::Clay | Self::Granite | Self::Crystal | Self::Basalt => true,
+            _ => false,
+        }
+    }
+    /// Pickaxe swings needed to break the tile
+    pub fn hardness(&self) -> usize {
+        match self {
+            Self::Dirt | Self::Sand | Self::Gravel => 1,
+            Self::Ladder | Self::Torch => 1,
+            Self::Stone | Self::Clay | Self::Block => 2,
+            Self::Granite | Self::Crystal => 3,
+            Self::Basalt => 4,
+            Self::Water | Self::Lava => 0,
         }
     }
 }
@@ -263,8 +495,11 @@ impl Tile {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ShopType {
     House,
-    Train,
+    /// Leads to the named server world, or between single player and the default one
+    Train(Option<String>),
     Passport,
+    /// Create, join or leave a team
+    Team,
     LeaderBoard,
     Info,
     Sell {
@@ -285,8 +520,9 @@ impl Shop {
         match &self.shop_type {
             ShopType::House => "Press E to customize yourself",
             ShopType::Passport => "Press E to change your name",
+            ShopType::Team => "Press E to create, join or leave a team",
             ShopType::Sell { .. } => "Press E to perform the deal",
-            ShopType::Train => "Press E to travel to the other world",
+            ShopType::Train(_) => "Press E to travel to the other world",
             ShopType::LeaderBoard => "Press E to view leader board",
             ShopType::Info => "Press E to see other controls :)",
         }
@@ -297,34 +533,164 @@ pub type TileMap = HashMap<Vec2<i32>, Tile>;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Model {
-    id_gen: IdGen,
+    pub(crate) id_gen: IdGen,
+    pub(crate) rng: ModelRng,
+    /// Used for world generation
+    pub(crate) seed: u64,
     pub ticks_per_second: f64,
     pub players: HashMap<Id, Player>,
     pub items: HashMap<Id, Item>,
+    pub creatures: HashMap<Id, Creature>,
     pub tiles: TileMap,
     pub shops: Vec<Shop>,
     pub leaderboard: HashMap<Id, Player>,
+    pub stats: HashMap<Id, PlayerStats>,
+    /// Teams are kept when everyone leaves, so they can be joined again by name
+    pub teams: HashMap<Id, Team>,
+    /// Seconds simulated, to know how long players have played
+    pub time: f64,
+    /// None when the world is not competitive
+    pub round: Option<Round>,
+    /// Fluid tiles that may be able to flow
+    pub(crate) active_fluids: HashSet<Vec2<i32>>,
+    fluid_timer: f64,
+    pub(crate) fluid_step: usize,
+    /// Loose tiles that may be able to fall
+    pub(crate) loose_tiles: HashSet<Vec2<i32>>,
+    gravity_timer: f64,
+    /// Whether wide unsupported ceilings collapse when mining under them
+    pub cave_ins: bool,
+    /// Only computed by clients that draw the world, see Model::enable_light
+    #[serde(skip)]
+    pub light: Option<LightMap>,
+}
+
+/// Group of players sharing their sales.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Team {
+    pub id: Id,
+    pub name: String,
+    /// Hat color worn by all members, kept apart from the other teams' colors
+    pub color: f64,
+    pub members: Vec<Id>,
+    pub treasury: usize,
 }
 
-const WORLD_SIZE: i32 = 200;
+impl Team {
+    pub const MAX_NAME_LENGTH: usize = 20;
+}
+
+/// Competitive round, at the end of which the world starts over.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Round {
+    pub number: usize,
+    /// Seconds
+    pub time_left: f64,
+}
+
+pub(crate) const WORLD_SIZE: i32 = 200;
+
+/// Round blobs scattered over the world by generation,
+/// at most one around a random point of every cell of a grid.
+pub(crate) struct Scatter {
+    /// Mixed into the seed, so different scatters use different cells
+    pub salt: u64,
+    pub cell_size: i32,
+    /// Percentage of the cells having a blob
+    pub chance: u64,
+    pub radius: Range<f32>,
+    /// How many times wider than tall the blobs are
+    pub stretch: f32,
+}
+
+impl Scatter {
+    /// Center of the blob the position is in, with the hash of its cell to pick more traits from.
+    /// Blobs whose center is not accepted are skipped.
+    pub(crate) fn find(
+        &self,
+        seed: u64,
+        position: Vec2<i32>,
+        accept: impl Fn(Vec2<i32>) -> bool,
+    ) -> Option<(Vec2<i32>, u64)> {
+        let seed = seed ^ self.salt;
+        let size = self.cell_size;
+        let cell = position.map(|x| x.div_euclid(size));
+        for cell_x in cell.x - 1..=cell.x + 1 {
+            for cell_y in cell.y - 1..=cell.y + 1 {
+                let hash = Self::hash(seed, cell_x, cell_y);
+                if hash % 100 >= self.chance {
+                    continue;
+                }
+                let center = vec2(
+                    cell_x * size + ((hash >> 8) % size as u64) as i32,
+                    cell_y * size + ((hash >> 16) % size as u64) as i32,
+                );
+                if !accept(center) {
+                    continue;
+                }
+                let radius = self.radius.start
+                    + ((hash >> 24) % 20) as f32 / 20.0 * (self.radius.end - self.radius.start);
+                let delta = (position - center).map(|x| x as f32);
+                if vec2(delta.x / self.stretch, delta.y).len() <= radius {
+                    return Some((center, hash));
+                }
+            }
+        }
+        None
+    }
+    fn hash(seed: u64, x: i32, y: i32) -> u64 {
+        let mut rng = ModelRng::new(
+            seed ^ (x as u32 as u64).wrapping_mul(0x9E3779B97F4A7C15)
+                ^ (y as u32 as u64).wrapping_mul(0xC2B2AE3D27D4EB4F),
+        );
+        rand::RngCore::next_u64(&mut rng)
+    }
+}
+
+/// Positions to move, lowest first so columns fall together.
+/// Sorted to stay deterministic, since set order differs between copies of the model.
+pub(crate) fn bottom_up(positions: HashSet<Vec2<i32>>) -> Vec<Vec2<i32>> {
+    let mut positions: Vec<Vec2<i32>> = positions.into_iter().collect();
+    positions.sort_by_key(|position| (position.y, position.x));
+    positions
+}
 
 impl Model {
-    pub fn new() -> Self {
+    pub const MAX_CHAT_LENGTH: usize = 100;
+
+    pub fn new(seed: u64) -> Self {
         Self {
             id_gen: IdGen::new(),
+            rng: ModelRng::new(seed),
+            seed,
             ticks_per_second: 20.0,
             players: default(),
             leaderboard: default(),
+            stats: default(),
+            teams: default(),
+            time: 0.0,
+            round: None,
+            active_fluids: default(),
+            fluid_timer: Self::FLUID_INTERVAL,
+            fluid_step: 0,
+            loose_tiles: default(),
+            gravity_timer: Self::GRAVITY_INTERVAL,
+            cave_ins: false,
+            light: None,
             tiles: {
                 let mut tiles = TileMap::new();
                 for x in -WORLD_SIZE..=WORLD_SIZE {
                     for y in -WORLD_SIZE..0 {
-                        tiles.insert(vec2(x, y), if y == -1 { Tile::Dirt } else { Tile::Stone });
+                        let position = vec2(x, y);
+                        if let Some(tile) = Self::generate_tile(seed, position) {
+                            tiles.insert(position, tile);
+                        }
                     }
                 }
                 tiles
             },
             items: default(),
+            creatures: default(),
             shops: vec![
                 Shop {
                     position: 0.0,
@@ -346,6 +712,18 @@ impl Model {
                         needs_coin: true,
                     },
                 },
+                Shop {
+                    position: 12.0,
+                    shop_type: ShopType::Team,
+                },
+                Shop {
+                    position: 16.0,
+                    shop_type: ShopType::Sell {
+                        require_item: ItemType::Block,
+                        give_item: Some(ItemType::Torch),
+                        needs_coin: true,
+                    },
+                },
                 Shop {
                     position: -4.0,
                     shop_type: ShopType::House,
@@ -360,17 +738,84 @@ impl Model {
                 },
                 Shop {
                     position: -16.0,
-                    shop_type: ShopType::Train,
+                    shop_type: ShopType::Train(None),
                 },
             ],
         }
     }
+    fn generate_tile(seed: u64, position: Vec2<i32>) -> Option<Tile> {
+        if position.x.abs() > WORLD_SIZE || position.y < -WORLD_SIZE || position.y >= 0 {
+            None
+        } else if let Some(fluid) = fluid::generate_pocket(seed, position) {
+            Some(fluid)
+        } else if biome::generate_cavern(seed, position) {
+            None
+        } else if let Some(loose) = gravity::generate_loose(seed, position) {
+            Some(loose)
+        } else {
+            Some(Biome::at_depth(-position.y).tile())
+        }
+    }
+    /// Puts the tiles in the rectangle back to how the world was generated
+    #[must_use]
+    pub fn reset_tiles(&mut self, min: Vec2<i32>, max: Vec2<i32>) -> Vec<Event> {
+        let mut events = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let position = vec2(x, y);
+                let tile = Self::generate_tile(self.seed, position);
+                let current = self.tiles.get(&position).copied();
+                if current == tile {
+                    continue;
+                }
+                if current.is_some() {
+                    events.push(Event::TileBroken(position));
+                }
+                if let Some(tile) = tile {
+                    events.push(Event::TilePlaced(position, tile));
+                }
+            }
+        }
+        for event in &events {
+            self.handle(event.clone());
+        }
+        events
+    }
+    /// Wakes up fluids and loose tiles that may be able to move after the tile changed,
+    /// and updates the light around it
+    pub fn tile_changed(&mut self, position: Vec2<i32>) {
+        self.wake_fluids(position);
+        self.wake_loose_tiles(position);
+        if let Some(light) = &mut self.light {
+            light.update(&self.tiles, position);
+        }
+    }
+    /// Starts keeping track of the light levels, for drawing
+    pub fn enable_light(&mut self) {
+        self.light = Some(LightMap::new(&self.tiles));
+    }
+    /// Changes a tile during simulation, recording it for a TilesChanged event
+    pub(crate) fn set_tile(
+        &mut self,
+        position: Vec2<i32>,
+        tile: Option<Tile>,
+        changes: &mut Vec<(Vec2<i32>, Option<Tile>)>,
+    ) {
+        match tile {
+            Some(tile) => self.tiles.insert(position, tile),
+            None => self.tiles.remove(&position),
+        };
+        changes.push((position, tile));
+        self.tile_changed(position);
+    }
     #[must_use]
     fn spawn_player(&mut self) -> (Id, Vec<Event>) {
-        let player = Player::new(&mut self.id_gen);
-        let events = vec![Event::PlayerJoined(player.clone())];
+        let player = Player::new(&mut self.id_gen, &mut self.rng);
+        let mut events = vec![Event::PlayerJoined(player.clone())];
         let player_id = player.id;
         self.players.insert(player_id, player);
+        let now = self.time;
+        self.update_stats(player_id, &mut events, |stats| stats.joined_at = Some(now));
         (player_id, events)
     }
     #[must_use]
@@ -378,43 +823,418 @@ impl Model {
         let (player_id, events) = self.spawn_player();
         (
             WelcomeMessage {
-                player_id,
+                player_id: Some(player_id),
                 model: self.clone(),
             },
             events,
         )
     }
+    pub fn spectate(&self) -> WelcomeMessage {
+        WelcomeMessage {
+            player_id: None,
+            model: self.clone(),
+        }
+    }
     #[must_use]
     pub fn drop_player(&mut self, player_id: Id) -> Vec<Event> {
-        self.players.remove(&player_id);
-        vec![Event::PlayerLeft(player_id)]
+        let mut events = Vec::new();
+        self.leave_team(player_id, &mut events);
+        if let Some(player) = self.players.remove(&player_id) {
+            let now = self.time;
+            self.update_stats(player_id, &mut events, |stats| {
+                stats.time_played = stats.total_time_played(now);
+                stats.joined_at = None;
+            });
+            if let Some(mut item) = player.item {
+                item.position = player.position;
+                let event = Event::ItemAdded(item);
+                events.push(event.clone());
+                self.handle_impl(event, None);
+            }
+        }
+        events.push(Event::PlayerLeft(player_id));
+        events
     }
     #[must_use]
     pub fn handle_message(
         &mut self,
         player_id: Id,
         message: ClientMessage,
-        // sender: &mut dyn geng::net::Sender<ServerMessage>,
-    ) -> Vec<Event> {
+    ) -> (Vec<Event>, Vec<ServerMessage>) {
         let mut events = Vec::new();
+        let mut replies = Vec::new();
         match message {
-            ClientMessage::Event(event) => {
-                self.handle_impl(event.clone(), Some(&mut events));
-                events.push(event);
+            ClientMessage::Event(Event::PlayerLeft(_)) => {
+                events.extend(self.drop_player(player_id));
+            }
+            // Chat has to go through ClientMessage::Chat to be checked,
+            // items through PickUp and DropItem,
+            // stats, teams and creatures are only changed here
+            ClientMessage::Event(Event::Chat(..))
+            | ClientMessage::Event(Event::ItemAdded(_))
+            | ClientMessage::Event(Event::ItemRemoved(_))
+            | ClientMessage::Event(Event::Announcement(_))
+            | ClientMessage::Event(Event::StatsUpdated(..))
+            | ClientMessage::Event(Event::TeamUpdated(_))
+            | ClientMessage::Event(Event::CreatureUpdated(_))
+            | ClientMessage::Event(Event::CreatureRemoved(_))
+            | ClientMessage::Event(Event::PlayerHurt(..))
+            | ClientMessage::Event(Event::ItemStolen(..))
+            | ClientMessage::Event(Event::TilesChanged(_)) => {}
+            // Tile edits are checked like predicted ones
+            ClientMessage::Event(event @ Event::TileBroken(_))
+            | ClientMessage::Event(event @ Event::TilePlaced(..))
+                if !self.can_apply(player_id, &event) => {}
+            ClientMessage::Event(Event::PlayerUpdated(update, _)) => {
+                if let Some(player) = self.players.get(&player_id) {
+                    let event = Event::PlayerUpdated(player.updated_by_client(update), self.time);
+                    self.apply_client_event(player_id, event, &mut events);
+                }
+            }
+            ClientMessage::Event(event) => self.apply_client_event(player_id, event, &mut events),
+            ClientMessage::Predicted(seq, event) => {
+                if self.can_apply(player_id, &event) {
+                    self.apply_client_event(player_id, event, &mut events);
+                    replies.push(ServerMessage::Confirmed(seq));
+                } else {
+                    replies.push(ServerMessage::Rejected(seq));
+                }
+            }
+            ClientMessage::PickUp(item_id) => match self.pick_up(player_id, item_id) {
+                Some(item) => {
+                    if item.item_type == ItemType::Chest {
+                        self.update_stats(player_id, &mut events, |stats| {
+                            stats.chests_found += 1;
+                        });
+                    }
+                    events.push(Event::ItemRemoved(item_id));
+                    replies.push(ServerMessage::PickedUp(item));
+                }
+                None => replies.push(ServerMessage::PickUpFailed(item_id)),
+            },
+            ClientMessage::DropItem => events.extend(self.drop_item(player_id)),
+            ClientMessage::Respawn => events.extend(self.respawn(player_id)),
+            ClientMessage::Team(name) => {
+                let name: String = name.trim().chars().take(Team::MAX_NAME_LENGTH).collect();
+                if !name.is_empty() {
+                    self.join_team(player_id, name, &mut events);
+                }
+            }
+            ClientMessage::LeaveTeam => self.leave_team(player_id, &mut events),
+            ClientMessage::Hit(creature_id) => {
+                events.extend(self.hit_creature(player_id, creature_id));
+            }
+            ClientMessage::Sell => self.sell(player_id, &mut events),
+            ClientMessage::Chat(text) => {
+                let text: String = text.trim().chars().take(Self::MAX_CHAT_LENGTH).collect();
+                if !text.is_empty() {
+                    events.push(Event::Chat(player_id, text));
+                }
+            }
+            // Handled by the connection
+            ClientMessage::Join(..)
+            | ClientMessage::Spectate(_)
+            | ClientMessage::Poll
+            | ClientMessage::Welcomed => {}
+        }
+        (events, replies)
+    }
+    fn apply_client_event(&mut self, player_id: Id, event: Event, events: &mut Vec<Event>) {
+        self.count_stats(player_id, &event, events);
+        if let Event::TilePlaced(..) = event {
+            // Placing uses up the held item
+            if let Some(player) = self.players.get_mut(&player_id) {
+                player.item = None;
             }
         }
+        self.handle_impl(event.clone(), Some(events));
+        events.push(event);
+    }
+    pub fn team_of(&self, player_id: Id) -> Option<&Team> {
+        self.teams
+            .values()
+            .find(|team| team.members.contains(&player_id))
+    }
+    fn update_team(&mut self, team: Team, events: &mut Vec<Event>) {
+        let event = Event::TeamUpdated(team);
+        self.handle_impl(event.clone(), None);
+        events.push(event);
+    }
+    /// Joins the team with the given name, or creates it
+    fn join_team(&mut self, player_id: Id, name: String, events: &mut Vec<Event>) {
+        self.leave_team(player_id, events);
+        let team = self
+            .teams
+            .values()
+            .find(|team| team.name.eq_ignore_ascii_case(&name))
+            .cloned();
+        let team = match team {
+            Some(mut team) => {
+                team.members.push(player_id);
+                team
+            }
+            None => Team {
+                id: self.id_gen.gen(),
+                name,
+                color: self.free_team_color(player_id),
+                members: vec![player_id],
+                treasury: 0,
+            },
+        };
+        self.update_team(team, events);
+    }
+    /// The hue furthest from the other teams', starting from the founder's hat color
+    fn free_team_color(&self, founder_id: Id) -> f64 {
+        let hat_color = self
+            .players
+            .get(&founder_id)
+            .map_or(0.0, |player| player.hat_color);
+        let distance = |hue: f64| {
+            self.teams
+                .values()
+                .map(|team| {
+                    let delta = (hue - team.color).abs();
+                    delta.min(1.0 - delta)
+                })
+                .fold(1.0, f64::min)
+        };
+        (0..36)
+            .map(|i| (hat_color + i as f64 / 36.0).fract())
+            .min_by_key(|&hue| r64(-distance(hue)))
+            .unwrap()
+    }
+    fn leave_team(&mut self, player_id: Id, events: &mut Vec<Event>) {
+        if let Some(team) = self.team_of(player_id) {
+            let mut team = team.clone();
+            team.members.retain(|&id| id != player_id);
+            self.update_team(team, events);
+        }
+    }
+    /// Changes the player's stats and sends them to everyone
+    fn update_stats(
+        &mut self,
+        player_id: Id,
+        events: &mut Vec<Event>,
+        f: impl FnOnce(&mut PlayerStats),
+    ) {
+        let stats = self.stats.entry(player_id).or_default();
+        f(stats);
+        events.push(Event::StatsUpdated(player_id, stats.clone()));
+    }
+    fn count_stats(&mut self, player_id: Id, event: &Event, events: &mut Vec<Event>) {
+        match event {
+            Event::PlayerUpdated(player, _) => {
+                // Whole tiles only, so walking around doesn't send stats every frame
+                let depth = (-player.position.y).floor();
+                let max_depth = self
+                    .stats
+                    .get(&player_id)
+                    .map_or(0.0, |stats| stats.max_depth);
+                if depth > max_depth {
+                    self.update_stats(player_id, events, |stats| stats.max_depth = depth);
+                }
+            }
+            Event::TileBroken(_) => {
+                self.update_stats(player_id, events, |stats| stats.tiles_broken += 1);
+            }
+            Event::TilePlaced(..) => {
+                self.update_stats(player_id, events, |stats| stats.blocks_placed += 1);
+            }
+            _ => {}
+        }
+    }
+    /// Drops the item the player holds according to the server at the player's position
+    fn drop_item(&mut self, player_id: Id) -> Vec<Event> {
+        let player = match self.players.get_mut(&player_id) {
+            Some(player) => player,
+            None => return vec![],
+        };
+        let mut item = match player.item.take() {
+            Some(item) => item,
+            None => return vec![],
+        };
+        item.position = player.position;
+        let event = Event::ItemAdded(item);
+        self.handle_impl(event.clone(), None);
+        vec![event]
+    }
+    /// Brings the player back at the surface, dropping the held item where the server last saw it
+    fn respawn(&mut self, player_id: Id) -> Vec<Event> {
+        let mut events = self.drop_item(player_id);
+        if let Some(player) = self.players.get(&player_id) {
+            let mut player = player.clone();
+            player.respawn();
+            let event = Event::PlayerUpdated(player, self.time);
+            self.handle_impl(event.clone(), None);
+            events.push(event);
+        }
         events
     }
+    /// Sells or trades the item the player holds according to the server, if the player is at a shop taking it.
+    /// Money from sales goes to the player and their team.
+    fn sell(&mut self, player_id: Id, events: &mut Vec<Event>) {
+        let mut player = match self.players.get(&player_id) {
+            Some(player) => player.clone(),
+            None => return,
+        };
+        let item = match player.item.take() {
+            Some(item) => item,
+            None => return,
+        };
+        let tolerance = Player::PICK_UP_TOLERANCE;
+        let shop = self.shops.iter().find_map(|shop| {
+            let delta = player.position + player.size - vec2(shop.position, 0.0);
+            let in_range = |x: f32| x >= -tolerance && x <= 2.0 + tolerance;
+            match shop.shop_type {
+                ShopType::Sell {
+                    require_item,
+                    give_item,
+                    needs_coin,
+                } if require_item == item.item_type && in_range(delta.x) && in_range(delta.y) => {
+                    Some((give_item, needs_coin))
+                }
+                _ => None,
+            }
+        });
+        let (give_item, needs_coin) = match shop {
+            Some(shop) => shop,
+            None => return,
+        };
+        if needs_coin {
+            if player.money == 0 {
+                return;
+            }
+            player.money -= 1;
+        }
+        let value = match give_item {
+            Some(item_type) => {
+                player.item = Some(Item {
+                    item_type,
+                    position: player.position,
+                    value: 0,
+                    ..item
+                });
+                0
+            }
+            None => item.value,
+        };
+        player.money = player.money.saturating_add(value);
+        let event = Event::PlayerUpdated(player, self.time);
+        self.handle_impl(event.clone(), None);
+        events.push(event);
+        if let Some(team) = self.team_of(player_id).filter(|_| value > 0) {
+            let mut team = team.clone();
+            team.treasury = team.treasury.saturating_add(value);
+            self.update_team(team, events);
+        }
+    }
+    fn pick_up(&mut self, player_id: Id, item_id: Id) -> Option<Item> {
+        let player = self.players.get_mut(&player_id)?;
+        let item = self.items.get(&item_id)?;
+        if (item.position - player.position).len() > Player::RANGE + Player::PICK_UP_TOLERANCE {
+            return None;
+        }
+        let item = self.items.remove(&item_id).unwrap();
+        player.item = Some(item.clone());
+        Some(item)
+    }
+    /// Whether a tile edit by the player can still be applied, only tile edits are predicted.
+    /// Placed tiles have to come from the held item and be within reach.
+    pub fn can_apply(&self, player_id: Id, event: &Event) -> bool {
+        match event {
+            Event::TileBroken(position) => self
+                .tiles
+                .get(position)
+                .map_or(false, |tile| !tile.is_fluid()),
+            Event::TilePlaced(position, tile) => {
+                let player = match self.players.get(&player_id) {
+                    Some(player) => player,
+                    None => return false,
+                };
+                let held = player
+                    .item
+                    .as_ref()
+                    .and_then(|item| item.item_type.placed());
+                let distance = ((player.position + player.size / 2.0)
+                    - position.map(|x| x as f32 + 0.5))
+                .len();
+                !self.tiles.contains_key(position)
+                    && held == Some(*tile)
+                    && distance < Player::RANGE + Player::PICK_UP_TOLERANCE
+            }
+            _ => false,
+        }
+    }
+    /// Starts over in a newly generated world, keeping the players but not their money
+    pub fn regenerate(&mut self, seed: u64) {
+        let generated = Self::new(seed);
+        self.rng = generated.rng;
+        self.seed = seed;
+        self.tiles = generated.tiles;
+        self.active_fluids.clear();
+        self.loose_tiles.clear();
+        if self.light.is_some() {
+            self.enable_light();
+        }
+        self.items.clear();
+        self.creatures.clear();
+        self.leaderboard.clear();
+        self.stats.clear();
+        for team in self.teams.values_mut() {
+            team.treasury = 0;
+        }
+        for player in self.players.values_mut() {
+            self.stats.insert(
+                player.id,
+                PlayerStats {
+                    joined_at: Some(self.time),
+                    ..default()
+                },
+            );
+            player.position = vec2(0.0, 0.0);
+            player.target_velocity = vec2(0.0, 0.0);
+            player.item = None;
+            player.money = 0;
+            self.leaderboard.insert(player.id, player.clone());
+        }
+    }
     #[must_use]
     pub fn tick(&mut self) -> Vec<Event> {
-        vec![]
+        let mut events = self.tick_creatures(1.0 / self.ticks_per_second as f32);
+        self.fluid_timer -= 1.0 / self.ticks_per_second;
+        if self.fluid_timer <= 0.0 {
+            self.fluid_timer += Self::FLUID_INTERVAL;
+            // Not applied again through handle_impl, flowing already changed the tiles
+            let changes = self.flow_fluids();
+            if !changes.is_empty() {
+                events.push(Event::TilesChanged(changes));
+            }
+        }
+        self.gravity_timer -= 1.0 / self.ticks_per_second;
+        if self.gravity_timer <= 0.0 {
+            self.gravity_timer += Self::GRAVITY_INTERVAL;
+            let mut hurt = Vec::new();
+            let changes = self.drop_loose_tiles(&mut hurt);
+            if !changes.is_empty() {
+                events.push(Event::TilesChanged(changes));
+            }
+            for event in hurt {
+                self.handle_impl(event.clone(), None);
+                events.push(event);
+            }
+        }
+        if let Some(round) = &mut self.round {
+            round.time_left = (round.time_left - 1.0 / self.ticks_per_second).max(0.0);
+        }
+        self.time += 1.0 / self.ticks_per_second;
+        events
     }
     pub fn handle(&mut self, event: Event) {
         self.handle_impl(event, None);
     }
     pub fn handle_impl(&mut self, event: Event, events: Option<&mut Vec<Event>>) {
         match event {
-            Event::PlayerJoined(player) | Event::PlayerUpdated(player) => {
+            Event::PlayerJoined(player) | Event::PlayerUpdated(player, _) => {
                 let player_id = player.id;
                 self.players.insert(player_id, player.clone());
                 self.leaderboard.insert(player_id, player);
@@ -422,37 +1242,76 @@ impl Model {
             Event::PlayerLeft(player_id) => {
                 self.players.remove(&player_id);
             }
+            Event::StatsUpdated(player_id, stats) => {
+                self.stats.insert(player_id, stats);
+            }
+            Event::TeamUpdated(team) => {
+                // The last member leaving ends the team
+                if team.members.is_empty() {
+                    self.teams.remove(&team.id);
+                } else {
+                    self.teams.insert(team.id, team);
+                }
+            }
+            Event::TilesChanged(changes) => {
+                for (position, tile) in changes {
+                    match tile {
+                        Some(tile) => self.tiles.insert(position, tile),
+                        None => self.tiles.remove(&position),
+                    };
+                    self.tile_changed(position);
+                }
+            }
+            Event::CreatureUpdated(creature) => {
+                self.creatures.insert(creature.id, creature);
+            }
+            Event::CreatureRemoved(creature_id) => {
+                self.creatures.remove(&creature_id);
+            }
+            Event::PlayerHurt(player_id, damage) => {
+                if let Some(player) = self.players.get_mut(&player_id) {
+                    player.damage(damage);
+                }
+            }
+            Event::ItemStolen(player_id, creature_id) => {
+                let item = self
+                    .players
+                    .get_mut(&player_id)
+                    .and_then(|player| player.item.take());
+                if let Some(creature) = self.creatures.get_mut(&creature_id) {
+                    creature.item = item;
+                }
+            }
             Event::TileBroken(position) => {
-                if let Some(tile) = self.tiles.remove(&position) {
+                let removed = self.tiles.remove(&position);
+                self.tile_changed(position);
+                if let Some(tile) = removed {
                     if let Some(events) = events {
-                        if global_rng().gen_bool(0.1) && (tile == Tile::Stone || tile == Tile::Dirt)
-                        {
-                            let mut item = Item::new(
-                                &mut self.id_gen,
-                                position.map(|x| x as f32)
-                                    + vec2(global_rng().gen_range(0.0..1.0), 0.0),
-                                ItemType::Chest,
-                            );
-                            item.value = (-position.y) as usize;
-                            let event = Event::ItemAdded(item);
-                            events.push(event.clone());
-                            self.handle_impl(event, None);
-                        } else {
-                            let event = Event::ItemAdded(Item::new(
-                                &mut self.id_gen,
-                                position.map(|x| x as f32)
-                                    + vec2(global_rng().gen_range(0.0..1.0), 0.0),
-                                ItemType::Block,
-                            ));
-                            events.push(event.clone());
-                            self.handle_impl(event, None);
+                        let changes = self.cave_in(position);
+                        if !changes.is_empty() {
+                            events.push(Event::TilesChanged(changes));
                         }
+                        let (item_type, value) = if tile.is_ground() {
+                            Biome::at_depth(-position.y).random_loot(-position.y, &mut self.rng)
+                        } else {
+                            (ItemType::Block, 0)
+                        };
+                        let mut item = Item::new(
+                            &mut self.id_gen,
+                            position.map(|x| x as f32) + vec2(self.rng.gen_range(0.0..1.0), 0.0),
+                            item_type,
+                        );
+                        item.value = value;
+                        let event = Event::ItemAdded(item);
+                        events.push(event.clone());
+                        self.handle_impl(event, None);
                     }
                 }
             }
             Event::TilePlaced(position, tile) => {
                 if !self.tiles.contains_key(&position) {
                     self.tiles.insert(position, tile);
+                    self.tile_changed(position);
                 }
             }
             Event::ItemAdded(item) => {
@@ -462,6 +1321,7 @@ impl Model {
             Event::ItemRemoved(id) => {
                 self.items.remove(&id);
             }
+            Event::Chat(..) | Event::Announcement(_) => {}
         }
     }
 }
@@ -469,10 +1329,302 @@ impl Model {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Event {
     PlayerJoined(Player),
-    PlayerUpdated(Player),
+    /// With the server's [Model::time] when it arrived, which clients interpolate on
+    PlayerUpdated(Player, f64),
     PlayerLeft(Id),
     TileBroken(Vec2<i32>),
     TilePlaced(Vec2<i32>, Tile),
     ItemAdded(Item),
     ItemRemoved(Id),
+    Chat(Id, String),
+    /// Message from the server admin to everyone
+    Announcement(String),
+    StatsUpdated(Id, PlayerStats),
+    TeamUpdated(Team),
+    CreatureUpdated(Creature),
+    CreatureRemoved(Id),
+    /// Players keep their own health, so they apply this themselves
+    PlayerHurt(Id, f32),
+    /// A creature took the item of a player
+    ItemStolen(Id, Id),
+    /// Tiles that were changed by fluids flowing or tiles falling, None means removed
+    TilesChanged(Vec<(Vec2<i32>, Option<Tile>)>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join(model: &mut Model) -> Id {
+        let (welcome, _) = model.welcome();
+        welcome.player_id.unwrap()
+    }
+
+    fn add_item(model: &mut Model, item_type: ItemType) -> Id {
+        let item = Item::new(&mut model.id_gen, vec2(0.0, 0.0), item_type);
+        let item_id = item.id;
+        model.handle(Event::ItemAdded(item));
+        item_id
+    }
+
+    #[test]
+    fn client_item_events_are_ignored() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let item = Item::new(&mut model.id_gen, vec2(0.0, 0.0), ItemType::Chest);
+        let item_id = item.id;
+        let (events, _) =
+            model.handle_message(player_id, ClientMessage::Event(Event::ItemAdded(item)));
+        assert!(events.is_empty());
+        assert!(!model.items.contains_key(&item_id));
+    }
+
+    #[test]
+    fn broken_tiles_are_applied_and_counted() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let position = *model
+            .tiles
+            .iter()
+            .find(|(_, tile)| !tile.is_fluid())
+            .unwrap()
+            .0;
+        let (events, _) =
+            model.handle_message(player_id, ClientMessage::Event(Event::TileBroken(position)));
+        assert!(!model.tiles.contains_key(&position));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::TileBroken(p) if *p == position)));
+        assert_eq!(model.stats[&player_id].tiles_broken, 1);
+    }
+
+    #[test]
+    fn forged_player_updates_only_move_the_player() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let item_count = model.items.len();
+        let mut forged = model.players[&player_id].clone();
+        forged.position = vec2(3.0, -2.0);
+        forged.item = Some(Item::new(
+            &mut model.id_gen,
+            vec2(0.0, 0.0),
+            ItemType::Chest,
+        ));
+        forged.money = 1000;
+        let _ = model.handle_message(
+            player_id,
+            ClientMessage::Event(Event::PlayerUpdated(forged, 0.0)),
+        );
+        let player = &model.players[&player_id];
+        assert_eq!(player.position, vec2(3.0, -2.0));
+        assert!(player.item.is_none());
+        assert_eq!(player.money, 0);
+        let (events, _) = model.handle_message(player_id, ClientMessage::DropItem);
+        assert!(events.is_empty());
+        assert_eq!(model.items.len(), item_count);
+    }
+
+    #[test]
+    fn only_the_held_item_is_placed_within_reach() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let place = |model: &mut Model, position: Vec2<i32>, tile: Tile| {
+            let event = Event::TilePlaced(position, tile);
+            let (_, replies) = model.handle_message(player_id, ClientMessage::Predicted(0, event));
+            matches!(replies[..], [ServerMessage::Confirmed(_)])
+        };
+        assert!(!model.tiles.contains_key(&vec2(0, 1)));
+        assert!(!place(&mut model, vec2(0, 1), Tile::Block));
+        let item_id = add_item(&mut model, ItemType::Block);
+        let _ = model.handle_message(player_id, ClientMessage::PickUp(item_id));
+        assert!(!place(&mut model, vec2(0, 1), Tile::Water));
+        assert!(!place(&mut model, vec2(10, 1), Tile::Block));
+        assert!(place(&mut model, vec2(0, 1), Tile::Block));
+        assert!(model.players[&player_id].item.is_none());
+        assert!(!place(&mut model, vec2(1, 1), Tile::Block));
+    }
+
+    #[test]
+    fn only_one_player_picks_up_an_item() {
+        let mut model = Model::new(0);
+        let first = join(&mut model);
+        let second = join(&mut model);
+        let item_id = add_item(&mut model, ItemType::Chest);
+        let (_, replies) = model.handle_message(first, ClientMessage::PickUp(item_id));
+        assert!(matches!(replies[..], [ServerMessage::PickedUp(ref item)] if item.id == item_id));
+        let (events, replies) = model.handle_message(second, ClientMessage::PickUp(item_id));
+        assert!(events.is_empty());
+        assert!(matches!(replies[..], [ServerMessage::PickUpFailed(id)] if id == item_id));
+        assert!(!model.items.contains_key(&item_id));
+        assert!(model.players[&second].item.is_none());
+    }
+
+    #[test]
+    fn dropped_player_leaves_item_and_team() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let item_id = add_item(&mut model, ItemType::Chest);
+        let _ = model.handle_message(player_id, ClientMessage::PickUp(item_id));
+        let _ = model.handle_message(player_id, ClientMessage::Team("diggers".to_owned()));
+        let events = model.drop_player(player_id);
+        assert!(!model.players.contains_key(&player_id));
+        assert!(model.items.contains_key(&item_id));
+        assert!(model.team_of(player_id).is_none());
+        assert!(matches!(events.last(), Some(Event::PlayerLeft(id)) if *id == player_id));
+    }
+
+    #[test]
+    fn sales_are_valued_by_the_server() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let _ = model.handle_message(player_id, ClientMessage::Team("diggers".to_owned()));
+        let mut chest = Item::new(&mut model.id_gen, vec2(0.0, 0.0), ItemType::Chest);
+        chest.value = 10;
+        let player = model.players.get_mut(&player_id).unwrap();
+        player.item = Some(chest);
+        let _ = model.handle_message(player_id, ClientMessage::Sell);
+        assert_eq!(model.team_of(player_id).unwrap().treasury, 0);
+        model.players.get_mut(&player_id).unwrap().position = vec2(4.0, 0.0);
+        let _ = model.handle_message(player_id, ClientMessage::Sell);
+        let _ = model.handle_message(player_id, ClientMessage::Sell);
+        assert_eq!(model.team_of(player_id).unwrap().treasury, 10);
+        assert!(model.players[&player_id].item.is_none());
+    }
+
+    #[test]
+    fn forged_items_are_not_sold() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let _ = model.handle_message(player_id, ClientMessage::Team("diggers".to_owned()));
+        let mut forged = model.players[&player_id].clone();
+        forged.position = vec2(4.0, 0.0);
+        let mut chest = Item::new(&mut model.id_gen, vec2(0.0, 0.0), ItemType::Chest);
+        chest.value = 10;
+        forged.item = Some(chest);
+        let _ = model.handle_message(
+            player_id,
+            ClientMessage::Event(Event::PlayerUpdated(forged, 0.0)),
+        );
+        let _ = model.handle_message(player_id, ClientMessage::Sell);
+        assert_eq!(model.team_of(player_id).unwrap().treasury, 0);
+        assert_eq!(model.players[&player_id].money, 0);
+    }
+
+    #[test]
+    fn empty_teams_are_removed() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        let _ = model.handle_message(player_id, ClientMessage::Team("diggers".to_owned()));
+        let _ = model.handle_message(player_id, ClientMessage::Team("miners".to_owned()));
+        assert_eq!(model.teams.len(), 1);
+        let _ = model.handle_message(player_id, ClientMessage::LeaveTeam);
+        assert!(model.teams.is_empty());
+    }
+
+    #[test]
+    fn teams_get_different_colors() {
+        let mut model = Model::new(0);
+        let first = join(&mut model);
+        let second = join(&mut model);
+        model.players.get_mut(&second).unwrap().hat_color = model.players[&first].hat_color;
+        let _ = model.handle_message(first, ClientMessage::Team("diggers".to_owned()));
+        let _ = model.handle_message(second, ClientMessage::Team("miners".to_owned()));
+        let colors: Vec<f64> = model.teams.values().map(|team| team.color).collect();
+        assert!((colors[0] - colors[1]).abs() > 0.4);
+    }
+
+    #[test]
+    fn time_played_stops_when_leaving() {
+        let mut model = Model::new(0);
+        let player_id = join(&mut model);
+        for _ in 0..2 * model.ticks_per_second as usize {
+            let events = model.tick();
+            assert!(!events
+                .iter()
+                .any(|event| matches!(event, Event::StatsUpdated(..))));
+        }
+        assert_eq!(model.stats[&player_id].total_time_played(model.time), 2);
+        let _ = model.drop_player(player_id);
+        for _ in 0..model.ticks_per_second as usize {
+            let _ = model.tick();
+        }
+        assert_eq!(model.stats[&player_id].total_time_played(model.time), 2);
+    }
+
+    #[test]
+    fn same_seed_and_events_give_same_world() {
+        let run = || {
+            let mut model = Model::new(7);
+            model.cave_ins = true;
+            let player_id = join(&mut model);
+            // Ground under fluids and loose tiles, so the world keeps moving
+            let mut targets: Vec<Vec2<i32>> = model
+                .tiles
+                .iter()
+                .filter(|&(&position, tile)| {
+                    tile.is_ground()
+                        && model
+                            .tiles
+                            .get(&(position + vec2(0, 1)))
+                            .map_or(false, |tile| tile.is_fluid() || tile.is_loose())
+                })
+                .map(|(&position, _)| position)
+                .collect();
+            targets.sort_by_key(|position| (position.y, position.x));
+            let mut log = Vec::new();
+            for (tick, &target) in targets.iter().take(50).enumerate() {
+                let (events, _) = model
+                    .handle_message(player_id, ClientMessage::Event(Event::TileBroken(target)));
+                log.push(format!("{} {:?}", tick, events));
+                for _ in 0..10 {
+                    log.push(format!("{} {:?}", tick, model.tick()));
+                }
+            }
+            (model, log)
+        };
+        let sorted = |values: Vec<String>| {
+            let mut values = values;
+            values.sort();
+            values
+        };
+        let (first, first_log) = run();
+        let (second, second_log) = run();
+        assert!(first_log
+            .iter()
+            .any(|events| events.contains("TilesChanged")));
+        assert_eq!(first_log, second_log);
+        assert_eq!(first.tiles, second.tiles);
+        assert_eq!(
+            sorted(
+                first
+                    .items
+                    .values()
+                    .map(|item| format!("{:?}", item))
+                    .collect()
+            ),
+            sorted(
+                second
+                    .items
+                    .values()
+                    .map(|item| format!("{:?}", item))
+                    .collect()
+            ),
+        );
+        assert_eq!(
+            sorted(
+                first
+                    .creatures
+                    .values()
+                    .map(|c| format!("{:?}", c))
+                    .collect()
+            ),
+            sorted(
+                second
+                    .creatures
+                    .values()
+                    .map(|c| format!("{:?}", c))
+                    .collect()
+            ),
+        );
+    }
 }
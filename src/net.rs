@@ -2,12 +2,50 @@ use super::*;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
+    /// First message of a connection, spawns a player in the named world or the default one
+    Join(Option<String>, Identity),
+    /// First message of a connection, only watches the world
+    Spectate(Option<String>),
+    /// Asks for new events without updating a player, used by spectators
+    Poll,
+    /// Acknowledges the welcome to a new round, the player's earlier updates were from the old world
+    Welcomed,
     Event(Event),
+    Predicted(u64, Event),
+    PickUp(Id),
+    /// Drops the held item where the player stands
+    DropItem,
+    /// Drops the held item and brings the player back at the surface with full health
+    Respawn,
+    Chat(String),
+    /// Joins the team with this name, creating it if there is none
+    Team(String),
+    LeaveTeam,
+    /// Sells or trades the held item at the shop the player stands at,
+    /// the money goes to the player and their team
+    Sell,
+    /// Swings the pickaxe at a creature
+    Hit(Id),
+}
+
+/// Random token that stays the same between sessions of a client, so it can be banned
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Identity(pub String);
+
+impl Identity {
+    pub fn random() -> Self {
+        Self(format!("{:016x}", global_rng().gen::<u64>()))
+    }
+    pub fn load() -> Self {
+        let mut identity = AutoSave::<Option<Identity>>::load("identity.json");
+        identity.get_or_insert_with(Self::random).clone()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WelcomeMessage {
-    pub player_id: Id,
+    /// None for spectators
+    pub player_id: Option<Id>,
     pub model: Model,
 }
 
@@ -15,13 +53,22 @@ pub struct WelcomeMessage {
 pub enum ServerMessage {
     Welcome(WelcomeMessage),
     Update(Vec<Event>),
+    Confirmed(u64),
+    Rejected(u64),
+    PickedUp(Item),
+    PickUpFailed(Id),
+    /// The server stopped handling this client, with the reason
+    Kicked(String),
 }
 
+#[cfg(feature = "net")]
 pub enum Connection {
     Local { next_tick: f64, model: Model },
     Remote(geng::net::client::Connection<ServerMessage, ClientMessage>),
+    Replay(Replay),
 }
 
+#[cfg(feature = "net")]
 impl Connection {
     pub fn is_local(&self) -> bool {
         match self {
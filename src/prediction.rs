@@ -0,0 +1,317 @@
+use super::*;
+
+struct PendingEdit {
+    seq: u64,
+    event: Event,
+    item: Option<Item>,
+}
+
+/// Applies the local player's world edits immediately and reconciles them
+/// once the server confirms or rejects them.
+///
+/// For every tile or item touched by a pending edit the last authoritative state is kept,
+/// so server events can be applied underneath the predictions and a rejected edit
+/// can be rolled back to whatever the server says is there.
+pub struct Prediction {
+    next_seq: u64,
+    pending: Vec<PendingEdit>,
+    tiles: HashMap<Vec2<i32>, Option<Tile>>,
+    items: HashMap<Id, Option<Item>>,
+}
+
+impl Prediction {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 0,
+            pending: Vec::new(),
+            tiles: HashMap::new(),
+            items: HashMap::new(),
+        }
+    }
+
+    /// Applies the edit locally and returns the message to send to the server.
+    ///
+    /// `item` is the held item spent on the edit, given back if the edit gets rejected.
+    pub fn predict(
+        &mut self,
+        model: &mut Model,
+        event: Event,
+        item: Option<Item>,
+    ) -> ClientMessage {
+        let seq = self.apply(model, event.clone(), item);
+        ClientMessage::Predicted(seq, event)
+    }
+
+    /// Hides the item locally and returns the pick up request to send to the server.
+    ///
+    /// The item only ends up in the player's hands once the server grants it.
+    pub fn pick_up(&mut self, model: &mut Model, item_id: Id) -> ClientMessage {
+        self.apply(model, Event::ItemRemoved(item_id), None);
+        ClientMessage::PickUp(item_id)
+    }
+
+    pub fn is_picking_up(&self) -> bool {
+        self.pending
+            .iter()
+            .any(|edit| matches!(edit.event, Event::ItemRemoved(_)))
+    }
+
+    fn apply(&mut self, model: &mut Model, event: Event, item: Option<Item>) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        match event {
+            Event::TileBroken(position) | Event::TilePlaced(position, _) => {
+                self.tiles
+                    .entry(position)
+                    .or_insert_with(|| model.tiles.get(&position).copied());
+            }
+            Event::ItemRemoved(id) => {
+                self.items
+                    .entry(id)
+                    .or_insert_with(|| model.items.get(&id).cloned());
+            }
+            _ => {}
+        }
+        model.handle(event.clone());
+        self.pending.push(PendingEdit { seq, event, item });
+        seq
+    }
+
+    /// Applies an authoritative event from the server, keeping pending edits on top of it.
+    pub fn handle(&mut self, model: &mut Model, event: Event) {
+        match event {
+            Event::TileBroken(position) | Event::TilePlaced(position, _)
+                if self.tiles.contains_key(&position) =>
+            {
+                let tile = self.tiles.get_mut(&position).unwrap();
+                *tile = Self::apply_tile(*tile, &event);
+                self.restore_tile(model, position);
+            }
+            Event::TilesChanged(changes) => {
+                let mut unpredicted = Vec::new();
+                for (position, tile) in changes {
+                    if let Some(authoritative) = self.tiles.get_mut(&position) {
+                        *authoritative = tile;
+                        self.restore_tile(model, position);
+                    } else {
+                        unpredicted.push((position, tile));
+                    }
+                }
+                if !unpredicted.is_empty() {
+                    model.handle(Event::TilesChanged(unpredicted));
+                }
+            }
+            Event::ItemAdded(ref item) if self.items.contains_key(&item.id) => {
+                self.items.insert(item.id, Some(item.clone()));
+                self.restore_item(model, item.id);
+            }
+            Event::ItemRemoved(id) if self.items.contains_key(&id) => {
+                self.items.insert(id, None);
+                self.restore_item(model, id);
+            }
+            _ => model.handle(event),
+        }
+    }
+
+    /// Finishes a pending edit.
+    ///
+    /// The authoritative events for an accepted edit always arrive before its confirmation,
+    /// so restoring the authoritative state is a no-op for accepted edits and a rollback for rejected ones.
+    /// Returns the item that was spent on a rolled back edit.
+    pub fn acknowledge(&mut self, model: &mut Model, seq: u64, accepted: bool) -> Option<Item> {
+        let index = self.pending.iter().position(|edit| edit.seq == seq)?;
+        self.finish(model, index, accepted)
+    }
+
+    /// Finishes a pending pick up once the server granted or refused it.
+    pub fn acknowledge_pick_up(&mut self, model: &mut Model, item_id: Id, accepted: bool) {
+        if let Some(index) = self
+            .pending
+            .iter()
+            .position(|edit| matches!(edit.event, Event::ItemRemoved(id) if id == item_id))
+        {
+            self.finish(model, index, accepted);
+        }
+    }
+
+    fn finish(&mut self, model: &mut Model, index: usize, accepted: bool) -> Option<Item> {
+        let edit = self.pending.remove(index);
+        match edit.event {
+            Event::TileBroken(position) | Event::TilePlaced(position, _) => {
+                self.restore_tile(model, position);
+                if !self.pending.iter().any(|edit| match edit.event {
+                    Event::TileBroken(other) | Event::TilePlaced(other, _) => other == position,
+                    _ => false,
+                }) {
+                    self.tiles.remove(&position);
+                }
+            }
+            Event::ItemRemoved(id) => {
+                self.restore_item(model, id);
+                if !self
+                    .pending
+                    .iter()
+                    .any(|edit| matches!(edit.event, Event::ItemRemoved(other) if other == id))
+                {
+                    self.items.remove(&id);
+                }
+            }
+            _ => {}
+        }
+        if accepted {
+            None
+        } else {
+            edit.item
+        }
+    }
+
+    fn apply_tile(tile: Option<Tile>, event: &Event) -> Option<Tile> {
+        match *event {
+            Event::TileBroken(_) => None,
+            Event::TilePlaced(_, placed) => tile.or(Some(placed)),
+            _ => tile,
+        }
+    }
+
+    fn restore_tile(&self, model: &mut Model, position: Vec2<i32>) {
+        let mut tile = self.tiles[&position];
+        for edit in &self.pending {
+            match edit.event {
+                Event::TileBroken(other) | Event::TilePlaced(other, _) if other == position => {
+                    tile = Self::apply_tile(tile, &edit.event);
+                }
+                _ => {}
+            }
+        }
+        match tile {
+            Some(tile) => {
+                model.tiles.insert(position, tile);
+            }
+            None => {
+                model.tiles.remove(&position);
+            }
+        }
+        model.tile_changed(position);
+    }
+
+    fn restore_item(&self, model: &mut Model, id: Id) {
+        let removed = self
+            .pending
+            .iter()
+            .any(|edit| matches!(edit.event, Event::ItemRemoved(other) if other == id));
+        match &self.items[&id] {
+            Some(item) if !removed => {
+                model.items.insert(id, item.clone());
+            }
+            _ => {
+                model.items.remove(&id);
+            }
+        }
+    }
+}
+
+impl Default for Prediction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Above the ground, where no tiles are generated
+    const POSITION: Vec2<i32> = Vec2 { x: 0, y: 5 };
+
+    fn sky() -> Model {
+        Model::new(0)
+    }
+
+    fn seq(message: &ClientMessage) -> u64 {
+        match message {
+            ClientMessage::Predicted(seq, _) => *seq,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn rejected_edit_is_rolled_back() {
+        let mut model = sky();
+        let mut prediction = Prediction::new();
+        let item = Item::new(&mut IdGen::new(), vec2(0.0, 0.0), ItemType::Block);
+        let message = prediction.predict(
+            &mut model,
+            Event::TilePlaced(POSITION, Tile::Block),
+            Some(item),
+        );
+        assert_eq!(model.tiles.get(&POSITION), Some(&Tile::Block));
+        let item = prediction.acknowledge(&mut model, seq(&message), false);
+        assert!(matches!(
+            item,
+            Some(Item {
+                item_type: ItemType::Block,
+                ..
+            })
+        ));
+        assert!(!model.tiles.contains_key(&POSITION));
+    }
+
+    #[test]
+    fn accepted_edit_stays() {
+        let mut model = sky();
+        let mut prediction = Prediction::new();
+        let event = Event::TilePlaced(POSITION, Tile::Block);
+        let message = prediction.predict(&mut model, event.clone(), None);
+        prediction.handle(&mut model, event);
+        assert!(prediction
+            .acknowledge(&mut model, seq(&message), true)
+            .is_none());
+        assert_eq!(model.tiles.get(&POSITION), Some(&Tile::Block));
+    }
+
+    #[test]
+    fn server_events_apply_under_pending_edits() {
+        let mut model = sky();
+        let mut prediction = Prediction::new();
+        let message = prediction.predict(&mut model, Event::TileBroken(POSITION), None);
+        // Someone else placed a tile there first, so the break gets rejected
+        prediction.handle(&mut model, Event::TilePlaced(POSITION, Tile::Ladder));
+        assert!(!model.tiles.contains_key(&POSITION));
+        prediction.acknowledge(&mut model, seq(&message), false);
+        assert_eq!(model.tiles.get(&POSITION), Some(&Tile::Ladder));
+    }
+
+    #[test]
+    fn flowing_tiles_apply_under_pending_edits() {
+        let mut model = sky();
+        let mut prediction = Prediction::new();
+        let message = prediction.predict(&mut model, Event::TileBroken(POSITION), None);
+        let next = POSITION + vec2(1, 0);
+        prediction.handle(
+            &mut model,
+            Event::TilesChanged(vec![
+                (POSITION, Some(Tile::Water)),
+                (next, Some(Tile::Water)),
+            ]),
+        );
+        assert!(!model.tiles.contains_key(&POSITION));
+        assert_eq!(model.tiles.get(&next), Some(&Tile::Water));
+        prediction.acknowledge(&mut model, seq(&message), false);
+        assert_eq!(model.tiles.get(&POSITION), Some(&Tile::Water));
+    }
+
+    #[test]
+    fn refused_pick_up_brings_the_item_back() {
+        let mut model = sky();
+        let mut prediction = Prediction::new();
+        let item = Item::new(&mut IdGen::new(), vec2(0.0, 1.0), ItemType::Chest);
+        let item_id = item.id;
+        model.handle(Event::ItemAdded(item));
+        prediction.pick_up(&mut model, item_id);
+        assert!(prediction.is_picking_up());
+        assert!(!model.items.contains_key(&item_id));
+        prediction.acknowledge_pick_up(&mut model, item_id, false);
+        assert!(!prediction.is_picking_up());
+        assert!(model.items.contains_key(&item_id));
+    }
+}
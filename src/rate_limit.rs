@@ -0,0 +1,231 @@
+use super::*;
+
+/// Allows bursts of up to `capacity` actions, refilling `rate` of them per second.
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_update: std::time::Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, rate: f64) -> Self {
+        Self {
+            capacity,
+            rate,
+            tokens: capacity,
+            last_update: std::time::Instant::now(),
+        }
+    }
+    /// Returns false if the bucket is empty
+    pub fn take(&mut self) -> bool {
+        self.take_at(std::time::Instant::now())
+    }
+    fn take_at(&mut self, now: std::time::Instant) -> bool {
+        let elapsed = (now - self.last_update).as_secs_f64();
+        self.last_update = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Join,
+    Poll,
+    PlayerUpdate,
+    TileEdit,
+    ItemEdit,
+    PickUp,
+    Chat,
+    Other,
+}
+
+impl MessageKind {
+    pub fn of(message: &ClientMessage) -> Self {
+        match message {
+            ClientMessage::Join(..) | ClientMessage::Spectate(_) => Self::Join,
+            ClientMessage::Poll => Self::Poll,
+            ClientMessage::Event(event) | ClientMessage::Predicted(_, event) => match event {
+                Event::PlayerUpdated(..) => Self::PlayerUpdate,
+                Event::TileBroken(_) | Event::TilePlaced(..) => Self::TileEdit,
+                Event::ItemAdded(_) | Event::ItemRemoved(_) => Self::ItemEdit,
+                _ => Self::Other,
+            },
+            ClientMessage::PickUp(_) => Self::PickUp,
+            ClientMessage::DropItem | ClientMessage::Respawn => Self::ItemEdit,
+            // Swings at creatures are as fast as swings at tiles
+            ClientMessage::Hit(_) => Self::TileEdit,
+            ClientMessage::Chat(_) => Self::Chat,
+            ClientMessage::Sell => Self::ItemEdit,
+            ClientMessage::Team(_) | ClientMessage::LeaveTeam | ClientMessage::Welcomed => {
+                Self::Other
+            }
+        }
+    }
+    /// Burst size and messages per second
+    fn limits(self) -> (f64, f64) {
+        match self {
+            // Clients answer every update, so these follow the frame rate
+            Self::Poll | Self::PlayerUpdate => (120.0, 90.0),
+            Self::TileEdit | Self::ItemEdit | Self::PickUp => (20.0, 8.0),
+            Self::Chat => (5.0, 1.0),
+            Self::Join | Self::Other => (10.0, 1.0),
+        }
+    }
+}
+
+pub const MAX_IDENTITY_LENGTH: usize = 64;
+pub const MAX_WORLD_NAME_LENGTH: usize = 64;
+
+/// Checks the size of the strings in a message, since those are sent on to everyone.
+pub fn check_message_size(message: &ClientMessage) -> Result<(), String> {
+    let check = |what: &str, value: &str, max_length: usize| {
+        if value.chars().count() > max_length {
+            Err(format!("{} is longer than {} characters", what, max_length))
+        } else {
+            Ok(())
+        }
+    };
+    match message {
+        ClientMessage::Join(world, identity) => {
+            check("Identity", &identity.0, MAX_IDENTITY_LENGTH)?;
+            check(
+                "World name",
+                world.as_deref().unwrap_or(""),
+                MAX_WORLD_NAME_LENGTH,
+            )
+        }
+        ClientMessage::Spectate(world) => check(
+            "World name",
+            world.as_deref().unwrap_or(""),
+            MAX_WORLD_NAME_LENGTH,
+        ),
+        ClientMessage::Chat(text) => check("Chat message", text, Model::MAX_CHAT_LENGTH),
+        ClientMessage::Team(name) => check("Team name", name, Team::MAX_NAME_LENGTH),
+        ClientMessage::Event(Event::PlayerJoined(player))
+        | ClientMessage::Event(Event::PlayerUpdated(player, _)) => {
+            check("Name", &player.name, Player::MAX_NAME_LENGTH)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Token buckets for every kind of message a client sends.
+pub struct RateLimiter {
+    buckets: HashMap<MessageKind, TokenBucket>,
+    violations: TokenBucket,
+}
+
+impl RateLimiter {
+    /// How many broken limits are forgiven at once, and per second
+    pub const VIOLATION_LIMITS: (f64, f64) = (50.0, 1.0);
+
+    pub fn new() -> Self {
+        let (capacity, rate) = Self::VIOLATION_LIMITS;
+        Self {
+            buckets: HashMap::new(),
+            violations: TokenBucket::new(capacity, rate),
+        }
+    }
+    /// Returns the message to handle, None if it is dropped,
+    /// or an error when the client keeps breaking limits and should be disconnected.
+    pub fn check(&mut self, message: ClientMessage) -> Result<Option<ClientMessage>, String> {
+        self.check_at(message, std::time::Instant::now())
+    }
+    fn check_at(
+        &mut self,
+        message: ClientMessage,
+        now: std::time::Instant,
+    ) -> Result<Option<ClientMessage>, String> {
+        let kind = MessageKind::of(&message);
+        let violation = match check_message_size(&message) {
+            Err(e) => e,
+            Ok(()) => {
+                let bucket = self.buckets.entry(kind).or_insert_with(|| {
+                    let (capacity, rate) = kind.limits();
+                    TokenBucket::new(capacity, rate)
+                });
+                if bucket.take_at(now) {
+                    return Ok(Some(message));
+                }
+                match kind {
+                    // Clients send these once per update, so screens faster than the limit
+                    // don't break it, they only have their new state dropped
+                    MessageKind::Poll | MessageKind::PlayerUpdate => {
+                        return Ok(Some(ClientMessage::Poll));
+                    }
+                    _ => format!("Too many {:?} messages", kind),
+                }
+            }
+        };
+        if self.violations.take_at(now) {
+            debug!("Dropped a message: {}", violation);
+            Ok(match kind {
+                // Clients wait for the update before sending the next one,
+                // so these still get one and only the new state is dropped
+                MessageKind::Poll | MessageKind::PlayerUpdate => Some(ClientMessage::Poll),
+                _ => None,
+            })
+        } else {
+            Err(violation)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_runs_out() {
+        let mut bucket = TokenBucket::new(3.0, 0.0);
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(!bucket.take());
+    }
+
+    #[test]
+    fn bucket_refills_up_to_capacity() {
+        let mut bucket = TokenBucket::new(2.0, 1000.0);
+        assert!(bucket.take());
+        assert!(bucket.take());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(bucket.tokens < 1.0);
+    }
+
+    #[test]
+    fn throttled_player_updates_still_poll() {
+        let mut limiter = RateLimiter::new();
+        let player = Player::new(&mut IdGen::new(), &mut global_rng());
+        let message = || ClientMessage::Event(Event::PlayerUpdated(player.clone(), 0.0));
+        let handled: Vec<ClientMessage> = (0..140)
+            .map(|_| limiter.check(message()).unwrap().unwrap())
+            .collect();
+        assert!(matches!(
+            handled[0],
+            ClientMessage::Event(Event::PlayerUpdated(..))
+        ));
+        assert!(matches!(handled[139], ClientMessage::Poll));
+        assert!(limiter.check(ClientMessage::Poll).unwrap().is_some());
+    }
+
+    #[test]
+    fn fast_screens_are_not_disconnected() {
+        let mut limiter = RateLimiter::new();
+        let player = Player::new(&mut IdGen::new(), &mut global_rng());
+        let start = std::time::Instant::now();
+        for frame in 0..144 * 60 {
+            let now = start + std::time::Duration::from_secs_f64(frame as f64 / 144.0);
+            let message = ClientMessage::Event(Event::PlayerUpdated(player.clone(), 0.0));
+            assert!(limiter.check_at(message, now).unwrap().is_some());
+        }
+    }
+}
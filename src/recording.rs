@@ -0,0 +1,156 @@
+use super::*;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecordedEvents {
+    pub time: f64,
+    pub events: Vec<Event>,
+    /// Set when the world started over for a new round, the events from here on apply to it
+    pub restart: Option<Model>,
+}
+
+/// Initial snapshot of the world followed by every event the server produced,
+/// and a new snapshot whenever a new round starts.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub model: Model,
+    pub events: Vec<RecordedEvents>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Recording {
+    pub fn load(path: impl AsRef<std::path::Path>) -> bincode::Result<Self> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let model = bincode::deserialize_from(&mut reader)?;
+        let mut events = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(recorded) => events.push(recorded),
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(ref e)
+                        if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    {
+                        break
+                    }
+                    _ => return Err(e),
+                },
+            }
+        }
+        Ok(Self { model, events })
+    }
+}
+
+/// Writes the server's event stream to a file, see [Recording].
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Recorder {
+    writer: std::io::BufWriter<std::fs::File>,
+    start: std::time::Instant,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Recorder {
+    pub fn create(path: impl AsRef<std::path::Path>, model: &Model) -> bincode::Result<Self> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        bincode::serialize_into(&mut writer, model)?;
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            start: std::time::Instant::now(),
+        })
+    }
+    pub fn record(&mut self, events: Vec<Event>) -> bincode::Result<()> {
+        self.write(RecordedEvents {
+            time: self.start.elapsed().as_secs_f64(),
+            events,
+            restart: None,
+        })
+    }
+    /// Snapshots the world after it was regenerated for a new round
+    pub fn restart(&mut self, model: &Model) -> bincode::Result<()> {
+        self.write(RecordedEvents {
+            time: self.start.elapsed().as_secs_f64(),
+            events: vec![],
+            restart: Some(model.clone()),
+        })
+    }
+    fn write(&mut self, recorded: RecordedEvents) -> bincode::Result<()> {
+        bincode::serialize_into(&mut self.writer, &recorded)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Plays a [Recording] back at variable speed.
+pub struct Replay {
+    events: std::vec::IntoIter<RecordedEvents>,
+    next: Option<RecordedEvents>,
+    pub time: f64,
+    pub speed: f64,
+    pub paused: bool,
+}
+
+impl Replay {
+    pub const MAX_SPEED: f64 = 16.0;
+
+    pub fn new(events: Vec<RecordedEvents>) -> Self {
+        let mut events = events.into_iter();
+        Self {
+            next: events.next(),
+            events,
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+        }
+    }
+    pub fn finished(&self) -> bool {
+        self.next.is_none()
+    }
+    /// Returns what the server sent in the meantime, as seen by a spectator
+    pub fn update(&mut self, delta_time: f64) -> Vec<ServerMessage> {
+        if !self.paused {
+            self.time += delta_time * self.speed;
+        }
+        let mut result = Vec::new();
+        while let Some(recorded) = self.next.take() {
+            if recorded.time > self.time {
+                self.next = Some(recorded);
+                break;
+            }
+            if let Some(model) = recorded.restart {
+                result.push(ServerMessage::Welcome(WelcomeMessage {
+                    player_id: None,
+                    model,
+                }));
+            }
+            result.push(ServerMessage::Update(recorded.events));
+            self.next = self.events.next();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_restarts_with_the_new_round() {
+        let recorded = |time: f64, restart: Option<Model>| RecordedEvents {
+            time,
+            events: vec![Event::Announcement(time.to_string())],
+            restart,
+        };
+        let mut next_round = Model::new(0);
+        next_round.regenerate(1);
+        let mut replay = Replay::new(vec![recorded(0.5, None), recorded(1.5, Some(next_round))]);
+        assert!(matches!(replay.update(1.0)[..], [ServerMessage::Update(_)]));
+        let messages = replay.update(1.0);
+        match &messages[..] {
+            [ServerMessage::Welcome(welcome), ServerMessage::Update(_)] => {
+                assert!(welcome.player_id.is_none());
+                assert_eq!(welcome.model.seed, 1);
+            }
+            _ => panic!("Expected a welcome, got {:?}", messages),
+        }
+        assert!(replay.finished());
+    }
+}
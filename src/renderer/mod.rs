@@ -43,6 +43,18 @@ impl Renderer {
         matrix: Mat4<f32>,
         texture: &ugli::Texture,
         color: Color<f32>,
+    ) {
+        self.draw_lit(framebuffer, camera, matrix, texture, color, 1.0);
+    }
+    /// Draws darkened by the light level between 0 and 1
+    pub fn draw_lit(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &Camera,
+        matrix: Mat4<f32>,
+        texture: &ugli::Texture,
+        color: Color<f32>,
+        light: f32,
     ) {
         let camera_uniforms = camera.uniforms(framebuffer.size().map(|x| x as f32));
         let uniforms = (
@@ -51,6 +63,7 @@ impl Renderer {
                 u_model_matrix: matrix,
                 u_texture: texture,
                 u_color: color,
+                u_light: light,
             },
         );
         ugli::draw(
@@ -16,7 +16,9 @@ void main() {
 #ifdef FRAGMENT_SHADER
 uniform sampler2D u_texture;
 uniform vec4 u_color;
+uniform float u_light;
 void main() {
     gl_FragColor = texture2D(u_texture, v_vt) * u_color;
+    gl_FragColor.rgb *= u_light;
 }
 #endif
\ No newline at end of file
@@ -1,154 +1,1137 @@
 use super::*;
 
+use std::sync::mpsc;
+
+/// Settings of one of the worlds hosted by a [Server].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSettings {
+    pub name: String,
+    /// Random by default
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default = "WorldSettings::default_ticks_per_second")]
+    pub ticks_per_second: f64,
+    /// Worlds reachable by train from this one
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// File the world is loaded from and saved to
+    #[serde(default)]
+    pub save: Option<String>,
+    /// Seconds per competitive round, the world starts over after each one
+    #[serde(default)]
+    pub round_duration: Option<f64>,
+    /// Directory to keep the results of finished rounds in
+    #[serde(default)]
+    pub round_archive: Option<String>,
+    /// Wide unsupported ceilings collapse into gravel
+    #[serde(default)]
+    pub cave_ins: bool,
+}
+
+impl WorldSettings {
+    fn default_ticks_per_second() -> f64 {
+        20.0
+    }
+    pub fn new(name: &str, seed: u64) -> Self {
+        Self {
+            name: name.to_owned(),
+            seed: Some(seed),
+            ticks_per_second: Self::default_ticks_per_second(),
+            links: Vec::new(),
+            save: None,
+            round_duration: None,
+            round_archive: None,
+            cave_ins: false,
+        }
+    }
+    pub fn create_model(&self) -> Model {
+        if let Some(path) = &self.save {
+            if std::path::Path::new(path).exists() {
+                let file = std::fs::File::open(path).expect("Failed to open the world save");
+                let mut model: Model = bincode::deserialize_from(std::io::BufReader::new(file))
+                    .expect("Failed to load the world save");
+                // Nobody is connected yet
+                model.players.clear();
+                model.ticks_per_second = self.ticks_per_second;
+                model.cave_ins = self.cave_ins;
+                // Continue the saved round
+                if self.round_duration.is_none() {
+                    model.round = None;
+                } else if model.round.is_none() {
+                    model.round = self.first_round();
+                }
+                // The links may have changed since the world was saved
+                model
+                    .shops
+                    .retain(|shop| !matches!(shop.shop_type, ShopType::Train(Some(_))));
+                self.add_links(&mut model);
+                return model;
+            }
+        }
+        let mut model = Model::new(self.seed.unwrap_or_else(|| global_rng().gen()));
+        model.ticks_per_second = self.ticks_per_second;
+        model.cave_ins = self.cave_ins;
+        model.round = self.first_round();
+        self.add_links(&mut model);
+        model
+    }
+    /// Adds a train station for every linked world
+    fn add_links(&self, model: &mut Model) {
+        for (index, link) in self.links.iter().enumerate() {
+            model.shops.push(Shop {
+                position: -20.0 - 4.0 * index as f32,
+                shop_type: ShopType::Train(Some(link.clone())),
+            });
+        }
+    }
+    fn first_round(&self) -> Option<Round> {
+        self.round_duration.map(|duration| Round {
+            number: 1,
+            time_left: duration,
+        })
+    }
+}
+
+/// Results of a finished round, as archived.
+#[derive(Serialize)]
+struct RoundResult<'a> {
+    world: &'a str,
+    round: usize,
+    /// Unix time in seconds
+    ended: u64,
+    leaderboard: &'a [LeaderboardEntry],
+}
+
+/// What the simulation thread sends to a client's writer thread.
+enum Outgoing {
+    /// New events of the world, kept until the next flush
+    Events(Arc<Vec<Event>>),
+    /// Sends the kept events as one update, followed by the replies
+    Flush(Vec<ServerMessage>),
+    Send(ServerMessage),
+}
+
+/// How many messages can wait for a client's writer before the client is dropped as too slow
+const OUTBOX_CAPACITY: usize = 1024;
+
+/// Encodes and sends messages to one client, so the simulation never waits on the network.
+fn spawn_writer(
+    mut sender: Box<dyn geng::net::Sender<ServerMessage>>,
+) -> mpsc::SyncSender<Outgoing> {
+    let (outbox, receiver) = mpsc::sync_channel(OUTBOX_CAPACITY);
+    std::thread::spawn(move || {
+        let mut events = Vec::new();
+        while let Ok(outgoing) = receiver.recv() {
+            match outgoing {
+                Outgoing::Events(batch) => events.extend(batch.iter().cloned()),
+                Outgoing::Flush(replies) => {
+                    sender.send(ServerMessage::Update(mem::replace(&mut events, Vec::new())));
+                    // Replies go after the update so the events they refer to are already delivered
+                    for reply in replies {
+                        sender.send(reply);
+                    }
+                }
+                Outgoing::Send(message) => sender.send(message),
+            }
+        }
+    });
+    outbox
+}
+
+enum WorldCommand {
+    Join {
+        client_id: Id,
+        /// None for spectators
+        identity: Option<Identity>,
+        outbox: mpsc::SyncSender<Outgoing>,
+    },
+    Message(Id, ClientMessage),
+    Leave(Id),
+    Run(Box<dyn FnOnce(&mut ServerState) + Send>),
+    Stop,
+}
+
+struct ConnectedClient {
+    player_id: Option<Id>,
+    outbox: mpsc::SyncSender<Outgoing>,
+    replies: Vec<ServerMessage>,
+    /// Events sent to the writer since the last flush
+    pending_events: usize,
+    /// Sent a new round's welcome, updates from the old world are ignored until it is acknowledged
+    awaiting_welcome: bool,
+}
+
+impl ConnectedClient {
+    /// Returns false if the outbox is full because the client can't keep up
+    fn send(&self, outgoing: Outgoing) -> bool {
+        !matches!(
+            self.outbox.try_send(outgoing),
+            Err(mpsc::TrySendError::Full(_))
+        )
+    }
+    fn flush(&mut self) -> bool {
+        self.pending_events = 0;
+        let replies = mem::replace(&mut self.replies, Vec::new());
+        self.send(Outgoing::Flush(replies))
+    }
+}
+
+/// A world and its clients, owned by the world's simulation thread.
 struct ServerState {
     model: Model,
-    events: std::collections::VecDeque<Event>,
-    next_event_index: usize,
-    first_event_index: usize,
-    clients_next_event: HashMap<Id, usize>,
+    clients: HashMap<Id, ConnectedClient>,
+    recorder: Option<Recorder>,
+    settings: WorldSettings,
+    /// Kept after players leave, for the leaderboard
+    identities: HashMap<Id, Identity>,
+    banned: Arc<Mutex<HashSet<String>>>,
+    /// How long the last tick took, in seconds
+    tick_time: f64,
 }
 
 impl ServerState {
-    fn new(model: Model) -> Self {
+    /// Ticks run at once to catch up after falling behind, the rest are skipped
+    const MAX_CATCH_UP_TICKS: usize = 5;
+
+    fn new(settings: WorldSettings, banned: Arc<Mutex<HashSet<String>>>) -> Self {
         Self {
-            model,
-            events: default(),
-            next_event_index: 0,
-            first_event_index: 0,
-            clients_next_event: default(),
+            model: settings.create_model(),
+            clients: default(),
+            recorder: None,
+            settings,
+            identities: default(),
+            banned,
+            tick_time: 0.0,
         }
     }
-    fn add_events(&mut self, events: impl IntoIterator<Item = Event>) {
-        for event in events.into_iter() {
-            // eprintln!("Add {}: {:?}", self.next_event_index, event);
-            self.events.push_back(event);
-            self.next_event_index += 1;
+    fn run(mut self, commands: mpsc::Receiver<WorldCommand>) {
+        let mut next_tick = std::time::Instant::now();
+        'run: loop {
+            let mut ticks = 0;
+            while std::time::Instant::now() >= next_tick {
+                if ticks == Self::MAX_CATCH_UP_TICKS {
+                    // Too far behind, the missed ticks are skipped
+                    next_tick = std::time::Instant::now();
+                    break;
+                }
+                self.tick();
+                next_tick += std::time::Duration::from_secs_f64(1.0 / self.model.ticks_per_second);
+                ticks += 1;
+            }
+            // Everything that arrived is handled between ticks, even when falling behind
+            let timeout = next_tick.saturating_duration_since(std::time::Instant::now());
+            let mut command = commands.recv_timeout(timeout);
+            loop {
+                match command {
+                    Ok(WorldCommand::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        break 'run;
+                    }
+                    Ok(command) => self.handle(command),
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                }
+                command = commands.try_recv().map_err(|e| match e {
+                    mpsc::TryRecvError::Empty => mpsc::RecvTimeoutError::Timeout,
+                    mpsc::TryRecvError::Disconnected => mpsc::RecvTimeoutError::Disconnected,
+                });
+            }
+        }
+        if let Some(path) = &self.settings.save {
+            match self.save() {
+                Ok(()) => info!("Saved the world to {}", path),
+                Err(e) => error!("Failed to save the world to {}: {}", path, e),
+            }
+        }
+    }
+    fn tick(&mut self) {
+        let start = std::time::Instant::now();
+        let events = self.model.tick();
+        self.add_events(events);
+        self.tick_time = start.elapsed().as_secs_f64();
+        if self
+            .model
+            .round
+            .as_ref()
+            .map_or(false, |round| round.time_left <= 0.0)
+        {
+            self.end_round();
+        }
+    }
+    fn handle(&mut self, command: WorldCommand) {
+        match command {
+            WorldCommand::Join {
+                client_id,
+                identity,
+                outbox,
+            } => self.join(client_id, identity, outbox),
+            WorldCommand::Message(client_id, message) => self.handle_message(client_id, message),
+            WorldCommand::Leave(client_id) => {
+                if let Some(client) = self.clients.remove(&client_id) {
+                    if let Some(player_id) = client.player_id {
+                        self.remove_player(player_id);
+                    }
+                }
+            }
+            WorldCommand::Run(f) => f(self),
+            WorldCommand::Stop => unreachable!(),
         }
     }
-    fn shrink(&mut self) {
-        let next_needed_event_index = self
-            .clients_next_event
+    /// Announces the winners, archives the results and starts the next round in a new world
+    fn end_round(&mut self) {
+        let number = self.model.round.as_ref().unwrap().number;
+        let leaderboard = self.leaderboard();
+        let winners: Vec<String> = leaderboard
+            .iter()
+            .take(3)
+            .enumerate()
+            .map(|(index, entry)| format!("{}. {} ({})", index + 1, entry.name, entry.money))
+            .collect();
+        let text = if winners.is_empty() {
+            format!("Round {} is over, nobody has played", number)
+        } else {
+            format!("Round {} is over! {}", number, winners.join(", "))
+        };
+        info!("{}: {}", self.settings.name, text);
+        self.add_events(vec![Event::Announcement(text)]);
+        if let Some(archive) = &self.settings.round_archive {
+            if let Err(e) = self.archive_round(archive, number, &leaderboard) {
+                error!("Failed to archive round {}: {}", number, e);
+            }
+        }
+
+        // The old world's events have to arrive before the new world
+        let slow = self
+            .clients
+            .iter_mut()
+            .filter_map(|(&client_id, client)| (!client.flush()).then_some(client_id))
+            .collect();
+        self.drop_slow_clients(slow);
+        self.model.regenerate(global_rng().gen());
+        self.model.round = Some(Round {
+            number: number + 1,
+            time_left: self.settings.round_duration.unwrap(),
+        });
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.restart(&self.model) {
+                error!("Failed to record the new round, recording stopped: {}", e);
+                self.recorder = None;
+            }
+        }
+        let model = &self.model;
+        let slow = self
+            .clients
+            .iter_mut()
+            .filter_map(|(&client_id, client)| {
+                client.awaiting_welcome = client.player_id.is_some();
+                let welcome = WelcomeMessage {
+                    player_id: client.player_id,
+                    model: model.clone(),
+                };
+                let sent = client.send(Outgoing::Send(ServerMessage::Welcome(welcome)));
+                (!sent).then_some(client_id)
+            })
+            .collect();
+        self.drop_slow_clients(slow);
+        self.add_events(vec![Event::Announcement(format!(
+            "Round {} has started",
+            number + 1
+        ))]);
+    }
+    fn archive_round(
+        &self,
+        archive: &str,
+        number: usize,
+        leaderboard: &[LeaderboardEntry],
+    ) -> std::io::Result<()> {
+        std::fs::create_dir_all(archive)?;
+        let result = RoundResult {
+            world: &self.settings.name,
+            round: number,
+            ended: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            leaderboard,
+        };
+        let path = std::path::Path::new(archive)
+            .join(format!("{}-round-{}.json", self.settings.name, number));
+        std::fs::write(path, serde_json::to_string_pretty(&result).unwrap())
+    }
+    fn save(&self) -> bincode::Result<()> {
+        let path = match &self.settings.save {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // Write to a temporary file first, so a crash never leaves a broken save
+        let temp_path = format!("{}.tmp", path);
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+        bincode::serialize_into(&mut writer, &self.model)?;
+        writer.flush()?;
+        std::fs::rename(temp_path, path)?;
+        Ok(())
+    }
+    fn add_events(&mut self, events: Vec<Event>) {
+        if events.is_empty() {
+            return;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(events.clone()) {
+                error!("Failed to record events, recording stopped: {}", e);
+                self.recorder = None;
+            }
+        }
+        let batch = Arc::new(events);
+        let slow = self
+            .clients
+            .iter_mut()
+            .filter_map(|(&client_id, client)| {
+                client.pending_events += batch.len();
+                (!client.send(Outgoing::Events(batch.clone()))).then_some(client_id)
+            })
+            .collect();
+        self.drop_slow_clients(slow);
+    }
+    /// Forgets clients that can't keep up with the world, their connections get nothing more
+    fn drop_slow_clients(&mut self, client_ids: Vec<Id>) {
+        for client_id in client_ids {
+            if let Some(client) = self.clients.remove(&client_id) {
+                warn!("Dropping client {:?}, it can't keep up", client_id);
+                if let Some(player_id) = client.player_id {
+                    self.remove_player(player_id);
+                }
+            }
+        }
+    }
+    fn join(
+        &mut self,
+        client_id: Id,
+        identity: Option<Identity>,
+        outbox: mpsc::SyncSender<Outgoing>,
+    ) {
+        let welcome = match identity {
+            Some(identity) => {
+                let (welcome, events) = self.model.welcome();
+                // The new client gets these as part of the welcome
+                self.add_events(events);
+                self.identities.insert(welcome.player_id.unwrap(), identity);
+                welcome
+            }
+            None => self.model.spectate(),
+        };
+        let mut client = ConnectedClient {
+            player_id: welcome.player_id,
+            outbox,
+            replies: Vec::new(),
+            pending_events: 0,
+            awaiting_welcome: false,
+        };
+        if client.send(Outgoing::Send(ServerMessage::Welcome(welcome))) && client.flush() {
+            self.clients.insert(client_id, client);
+        } else if let Some(player_id) = client.player_id {
+            self.remove_player(player_id);
+        }
+    }
+    fn handle_message(&mut self, client_id: Id, message: ClientMessage) {
+        // Messages from kicked clients can still be on the way
+        let client = match self.clients.get_mut(&client_id) {
+            Some(client) => client,
+            None => return,
+        };
+        let player_id = match client.player_id {
+            Some(player_id) => player_id,
+            None => {
+                match message {
+                    ClientMessage::Poll => {
+                        if !client.flush() {
+                            self.drop_slow_clients(vec![client_id]);
+                        }
+                    }
+                    message => warn!("Spectator sent {:?}", message),
+                }
+                return;
+            }
+        };
+        if client.awaiting_welcome {
+            match message {
+                ClientMessage::Welcomed => client.awaiting_welcome = false,
+                // Still answered, clients wait for an update before sending the next one
+                ClientMessage::Poll | ClientMessage::Event(Event::PlayerUpdated(..)) => {
+                    if !client.flush() {
+                        self.drop_slow_clients(vec![client_id]);
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+        if let ClientMessage::Event(Event::PlayerUpdated(player, _)) = &message {
+            if is_banned(&self.banned, &player.name) {
+                self.kick(player_id, "This name is banned");
+                return;
+            }
+        }
+        let send_update = matches!(
+            message,
+            ClientMessage::Poll | ClientMessage::Event(Event::PlayerUpdated(..))
+        );
+        let (events, replies) = self.model.handle_message(player_id, message);
+        self.add_events(events);
+        // Dropped if it couldn't keep up with the events
+        let client = match self.clients.get_mut(&client_id) {
+            Some(client) => client,
+            None => return,
+        };
+        client.replies.extend(replies);
+        if send_update && !client.flush() {
+            self.drop_slow_clients(vec![client_id]);
+        }
+    }
+    fn remove_player(&mut self, player_id: Id) {
+        let events = self.model.drop_player(player_id);
+        self.add_events(events);
+    }
+    fn kick(&mut self, player_id: Id, reason: &str) {
+        info!("Kicking player {:?}: {}", player_id, reason);
+        let client_id = self
+            .clients
+            .iter()
+            .find(|(_, client)| client.player_id == Some(player_id))
+            .map(|(&client_id, _)| client_id);
+        if let Some(client_id) = client_id {
+            let client = self.clients.remove(&client_id).unwrap();
+            client.send(Outgoing::Send(ServerMessage::Kicked(reason.to_owned())));
+        }
+        self.remove_player(player_id);
+    }
+    /// Kicks every player with the given id, name or identity, returns how many
+    fn kick_matching(&mut self, target: &str, reason: &str) -> usize {
+        let player_ids: Vec<Id> = self
+            .model
+            .players
             .values()
-            .map(|&index| index)
-            .min()
-            .unwrap_or(self.next_event_index);
-        while self.first_event_index < next_needed_event_index {
-            self.events.pop_front();
-            self.first_event_index += 1;
+            .filter(|player| {
+                player.id.raw().to_string() == target
+                    || player.name.eq_ignore_ascii_case(target)
+                    || self
+                        .identities
+                        .get(&player.id)
+                        .map_or(false, |identity| identity.0.eq_ignore_ascii_case(target))
+            })
+            .map(|player| player.id)
+            .collect();
+        for &player_id in &player_ids {
+            self.kick(player_id, reason);
+        }
+        player_ids.len()
+    }
+    /// Identities of the players with the given id, their names if they have none
+    fn identities_of(&self, id: &str) -> Vec<String> {
+        self.model
+            .players
+            .values()
+            .filter(|player| player.id.raw().to_string() == id)
+            .map(|player| match self.identities.get(&player.id) {
+                Some(identity) => identity.0.to_lowercase(),
+                None => player.name.to_lowercase(),
+            })
+            .collect()
+    }
+    fn list(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        for player in self.model.players.values() {
+            result.push(format!(
+                "  {} {:?} money: {} identity: {}",
+                player.id.raw(),
+                player.name,
+                player.money,
+                self.identities
+                    .get(&player.id)
+                    .map_or("-", |identity| identity.0.as_str()),
+            ));
+        }
+        result
+    }
+    fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let mut result: Vec<LeaderboardEntry> = self
+            .model
+            .leaderboard
+            .values()
+            .map(|player| LeaderboardEntry {
+                name: player.name.clone(),
+                money: player.money,
+                team: self.model.team_of(player.id).map(|team| team.name.clone()),
+                stats: {
+                    let mut stats = self
+                        .model
+                        .stats
+                        .get(&player.id)
+                        .cloned()
+                        .unwrap_or_default();
+                    // Exported with the current session counted in
+                    stats.time_played = stats.total_time_played(self.model.time);
+                    stats.joined_at = None;
+                    stats
+                },
+                identity: self
+                    .identities
+                    .get(&player.id)
+                    .map(|identity| identity.0.clone()),
+            })
+            .collect();
+        result.sort_by(|a, b| b.money.cmp(&a.money).then_with(|| a.name.cmp(&b.name)));
+        result
+    }
+    fn status(&self, name: &str) -> WorldStatus {
+        WorldStatus {
+            name: name.to_owned(),
+            players: self.model.players.len(),
+            spectators: self
+                .clients
+                .values()
+                .filter(|client| client.player_id.is_none())
+                .count(),
+            ticks_per_second: self.model.ticks_per_second,
+            tick_time: self.tick_time,
+            event_backlog: self
+                .clients
+                .values()
+                .map(|client| client.pending_events)
+                .sum(),
+            items: self.model.items.len(),
+            tiles: self.model.tiles.len(),
+        }
+    }
+}
+
+/// One row of a world's leaderboard, as exported to JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub money: usize,
+    pub team: Option<String>,
+    #[serde(flatten)]
+    pub stats: PlayerStats,
+    /// None for players from a world save, whose identity is not known
+    pub identity: Option<String>,
+}
+
+/// Health of one world, served by the status endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorldStatus {
+    pub name: String,
+    pub players: usize,
+    pub spectators: usize,
+    pub ticks_per_second: f64,
+    /// Seconds the last tick took
+    pub tick_time: f64,
+    /// Events waiting to be sent to clients
+    pub event_backlog: usize,
+    pub items: usize,
+    pub tiles: usize,
+}
+
+impl WorldStatus {
+    /// Formats the statuses as Prometheus text metrics
+    pub fn prometheus(statuses: &[WorldStatus]) -> String {
+        let metrics: [(&str, &str, fn(&WorldStatus) -> f64); 7] = [
+            ("players", "Connected players", |status| {
+                status.players as f64
+            }),
+            ("spectators", "Connected spectators", |status| {
+                status.spectators as f64
+            }),
+            ("ticks_per_second", "Target tick rate", |status| {
+                status.ticks_per_second
+            }),
+            ("tick_time_seconds", "Duration of the last tick", |status| {
+                status.tick_time
+            }),
+            (
+                "event_backlog",
+                "Events waiting to be sent to clients",
+                |status| status.event_backlog as f64,
+            ),
+            ("items", "Items in the world", |status| status.items as f64),
+            ("tiles", "Tiles in the world", |status| status.tiles as f64),
+        ];
+        let mut result = String::new();
+        for (name, help, value) in metrics.iter() {
+            result += &format!("# HELP ludumdare48_{} {}\n", name, help);
+            result += &format!("# TYPE ludumdare48_{} gauge\n", name);
+            for status in statuses {
+                result += &format!(
+                    "ludumdare48_{}{{world={:?}}} {}\n",
+                    name,
+                    status.name,
+                    value(status),
+                );
+            }
         }
+        result
     }
-    fn get_new_events(&mut self, player_id: Id) -> Vec<Event> {
-        let next_event_index = self.clients_next_event[&player_id];
+}
+
+fn is_banned(banned: &Mutex<HashSet<String>>, name_or_identity: &str) -> bool {
+    banned
+        .lock()
+        .unwrap()
+        .contains(&name_or_identity.to_lowercase())
+}
+
+struct WorldHandle {
+    sender: Mutex<mpsc::Sender<WorldCommand>>,
+}
+
+impl WorldHandle {
+    fn sender(&self) -> mpsc::Sender<WorldCommand> {
+        self.sender.lock().unwrap().clone()
+    }
+    fn send(&self, command: WorldCommand) {
+        let _ = self.sender.lock().unwrap().send(command);
+    }
+    /// Runs the function on the simulation thread and waits for the result
+    fn run<R: Send + 'static>(&self, f: impl FnOnce(&mut ServerState) -> R + Send + 'static) -> R {
+        let (sender, receiver) = mpsc::channel();
+        self.send(WorldCommand::Run(Box::new(move |server_state| {
+            let _ = sender.send(f(server_state));
+        })));
+        receiver.recv().expect("World thread has stopped")
+    }
+}
+
+struct Worlds {
+    default: String,
+    states: HashMap<String, WorldHandle>,
+    /// Lowercase names and identities
+    banned: Arc<Mutex<HashSet<String>>>,
+}
+
+const CONSOLE_HELP: &str = "Commands:
+  list                                   show players in all worlds
+  kick <player> [reason]                 kick a player by id, name or identity
+  ban <player>                           kick and refuse a player by id, name or identity
+  unban <name or identity>
+  say <message>                          announce a message in all worlds
+  tps <value> [world]                    change the ticks per second
+  save                                   save worlds that have a save file
+  reset <x1> <y1> <x2> <y2> [world]      regenerate a region of tiles";
+
+impl Worlds {
+    fn world(&self, name: Option<&str>) -> Result<&WorldHandle, String> {
+        let name = name.unwrap_or(&self.default);
+        self.states
+            .get(name)
+            .ok_or_else(|| format!("No world named {:?}", name))
+    }
+    fn get(&self, name: Option<&str>) -> &WorldHandle {
+        match self.world(name) {
+            Ok(world) => world,
+            Err(e) => {
+                warn!("{}, joining {:?}", e, self.default);
+                &self.states[&self.default]
+            }
+        }
+    }
+    fn save(&self) -> String {
         let mut result = Vec::new();
-        for index in next_event_index..self.next_event_index {
-            result.push(self.events[index - self.first_event_index].clone());
+        for (name, world) in &self.states {
+            let saved = world.run(|server_state| {
+                server_state
+                    .settings
+                    .save
+                    .clone()
+                    .map(|path| (path, server_state.save()))
+            });
+            match saved {
+                None => {}
+                Some((path, Ok(()))) => result.push(format!("Saved {} to {}", name, path)),
+                Some((path, Err(e))) => {
+                    result.push(format!("Failed to save {} to {}: {}", name, path, e))
+                }
+            }
+        }
+        if result.is_empty() {
+            "No world has a save file".to_owned()
+        } else {
+            result.join("\n")
+        }
+    }
+    /// Executes an admin console command and returns the text to show
+    fn execute(&self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (&command, args) = match words.split_first() {
+            Some(split) => split,
+            None => return String::new(),
+        };
+        let result = match (command, args) {
+            ("help", _) => Ok(CONSOLE_HELP.to_owned()),
+            ("list", []) => Ok(self.list()),
+            ("kick", [target, reason @ ..]) => {
+                let reason = if reason.is_empty() {
+                    "Kicked by an admin".to_owned()
+                } else {
+                    reason.join(" ")
+                };
+                match self.kick(target, &reason) {
+                    0 => Err(format!("No player {:?}", target)),
+                    count => Ok(format!("Kicked {} players", count)),
+                }
+            }
+            ("ban", [target]) => {
+                // Ids change with every connection, so the player's identity is banned instead
+                let targets = if target.parse::<u64>().is_ok() {
+                    self.identities_of(target)
+                } else {
+                    vec![target.to_lowercase()]
+                };
+                if targets.is_empty() {
+                    Err(format!("No player with id {}", target))
+                } else {
+                    self.banned.lock().unwrap().extend(targets.iter().cloned());
+                    let count: usize = targets
+                        .iter()
+                        .map(|target| self.kick(target, "Banned by an admin"))
+                        .sum();
+                    Ok(format!("Banned {:?}, kicked {} players", targets, count))
+                }
+            }
+            ("unban", [target]) => {
+                if self.banned.lock().unwrap().remove(&target.to_lowercase()) {
+                    Ok(format!("Unbanned {:?}", target))
+                } else {
+                    Err(format!("{:?} is not banned", target))
+                }
+            }
+            ("say", message) if !message.is_empty() => {
+                let message = message.join(" ");
+                for world in self.states.values() {
+                    let message = message.clone();
+                    world.run(move |server_state| {
+                        server_state.add_events(vec![Event::Announcement(message)])
+                    });
+                }
+                Ok(format!("Announced {:?}", message))
+            }
+            ("tps", [value, world @ ..]) if world.len() <= 1 => {
+                match (value.parse::<f64>(), self.world(world.first().copied())) {
+                    (Ok(value), Ok(world)) if value > 0.0 => {
+                        world.run(move |server_state| server_state.model.ticks_per_second = value);
+                        Ok(format!("Ticks per second set to {}", value))
+                    }
+                    (_, Err(e)) => Err(e),
+                    _ => Err(format!("Bad ticks per second {:?}", value)),
+                }
+            }
+            ("save", []) => Ok(self.save()),
+            ("reset", [x1, y1, x2, y2, world @ ..]) if world.len() <= 1 => {
+                // Nothing is generated outside the world, so a huge region
+                // would only stall the world's thread
+                let coordinates: Result<Vec<i32>, _> = [x1, y1, x2, y2]
+                    .iter()
+                    .map(|x| x.parse::<i32>().map(|x| x.clamp(-WORLD_SIZE, WORLD_SIZE)))
+                    .collect();
+                match (coordinates, self.world(world.first().copied())) {
+                    (Ok(coordinates), Ok(world)) => {
+                        let min = vec2(
+                            coordinates[0].min(coordinates[2]),
+                            coordinates[1].min(coordinates[3]),
+                        );
+                        let max = vec2(
+                            coordinates[0].max(coordinates[2]),
+                            coordinates[1].max(coordinates[3]),
+                        );
+                        let count = world.run(move |server_state| {
+                            let events = server_state.model.reset_tiles(min, max);
+                            let count = events.len();
+                            server_state.add_events(events);
+                            count
+                        });
+                        Ok(format!("Reset tiles, {} changes", count))
+                    }
+                    (_, Err(e)) => Err(e),
+                    (Err(e), _) => Err(format!("Bad coordinates: {}", e)),
+                }
+            }
+            _ => Err(format!("Unknown command {:?}, try help", line)),
+        };
+        match result {
+            Ok(text) => text,
+            Err(text) => format!("Error: {}", text),
         }
-        self.clients_next_event
-            .insert(player_id, self.next_event_index);
-        self.shrink();
-        // eprintln!("{:?}: {:?}", player_id, result);
+    }
+    fn list(&self) -> String {
+        let mut result = Vec::new();
+        for (name, world) in &self.states {
+            let (ticks_per_second, players) = world
+                .run(|server_state| (server_state.model.ticks_per_second, server_state.list()));
+            result.push(format!(
+                "{} ({} players, {} ticks per second):",
+                name,
+                players.len(),
+                ticks_per_second,
+            ));
+            result.extend(players);
+        }
+        result.join("\n")
+    }
+    fn status(&self) -> Vec<WorldStatus> {
+        let mut result: Vec<WorldStatus> = self
+            .states
+            .iter()
+            .map(|(name, world)| {
+                let name = name.clone();
+                world.run(move |server_state| server_state.status(&name))
+            })
+            .collect();
+        result.sort_by(|a, b| a.name.cmp(&b.name));
         result
     }
+    /// Leaderboards of all worlds by world name, best first
+    fn leaderboards(&self) -> HashMap<String, Vec<LeaderboardEntry>> {
+        self.states
+            .iter()
+            .map(|(name, world)| {
+                (
+                    name.clone(),
+                    world.run(|server_state| server_state.leaderboard()),
+                )
+            })
+            .collect()
+    }
+    /// Answers requests to the status endpoint
+    fn serve(&self, path: &str) -> Option<http::Response> {
+        match path {
+            "/leaderboard" => Some(http::Response::json(&self.leaderboards())),
+            "/" | "/status" => Some(http::Response::json(&self.status())),
+            "/metrics" => Some(http::Response::text(WorldStatus::prometheus(
+                &self.status(),
+            ))),
+            _ => None,
+        }
+    }
+    fn identities_of(&self, id: &str) -> Vec<String> {
+        self.states
+            .values()
+            .flat_map(|world| {
+                let id = id.to_owned();
+                world.run(move |server_state| server_state.identities_of(&id))
+            })
+            .collect()
+    }
+    fn kick(&self, target: &str, reason: &str) -> usize {
+        self.states
+            .values()
+            .map(|world| {
+                let target = target.to_owned();
+                let reason = reason.to_owned();
+                world.run(move |server_state| server_state.kick_matching(&target, &reason))
+            })
+            .sum()
+    }
 }
 
+/// Receives messages of one connection and passes them to its world.
 struct Client {
-    player_id: Id,
-    server_state: Arc<Mutex<ServerState>>,
-    sender: Box<dyn geng::net::Sender<ServerMessage>>,
+    id: Id,
+    worlds: Arc<Worlds>,
+    world: Option<mpsc::Sender<WorldCommand>>,
+    outbox: mpsc::SyncSender<Outgoing>,
+    rate_limiter: rate_limit::RateLimiter,
+    kicked: bool,
+}
+
+impl Client {
+    fn disconnect(&mut self, reason: &str) {
+        warn!("Disconnecting client {:?}: {}", self.id, reason);
+        if let Some(world) = &self.world {
+            let _ = world.send(WorldCommand::Leave(self.id));
+        }
+        // Never waits for a full outbox, the kick is only a courtesy
+        let _ = self
+            .outbox
+            .try_send(Outgoing::Send(ServerMessage::Kicked(reason.to_owned())));
+        self.kicked = true;
+    }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
-        let mut server_state = self.server_state.lock().unwrap();
-        let events = server_state.model.drop_player(self.player_id);
-        server_state.add_events(events);
+        if let Some(world) = &self.world {
+            if !self.kicked {
+                let _ = world.send(WorldCommand::Leave(self.id));
+            }
+        }
     }
 }
 
 impl geng::net::Receiver<ClientMessage> for Client {
     fn handle(&mut self, message: ClientMessage) {
-        let send_update = matches!(message, ClientMessage::Event(Event::PlayerUpdated(_)));
-        let mut server_state = self.server_state.lock().unwrap();
-        let events = server_state.model.handle_message(
-            self.player_id,
-            message,
-            // &mut *self.sender
-        );
-        server_state.add_events(events);
-        if send_update {
-            self.sender.send(ServerMessage::Update(
-                server_state.get_new_events(self.player_id),
-            ));
+        if self.kicked {
+            return;
+        }
+        let message = match self.rate_limiter.check(message) {
+            Ok(Some(message)) => message,
+            Ok(None) => return,
+            Err(reason) => {
+                self.disconnect(&reason);
+                return;
+            }
+        };
+        if let Some(world) = &self.world {
+            let _ = world.send(WorldCommand::Message(self.id, message));
+            return;
         }
+        let (world, identity) = match message {
+            ClientMessage::Join(world, identity) => (world, Some(identity)),
+            ClientMessage::Spectate(world) => (world, None),
+            message => {
+                warn!("Client sent {:?} before joining", message);
+                return;
+            }
+        };
+        if let Some(identity) = &identity {
+            if is_banned(&self.worlds.banned, &identity.0) {
+                self.disconnect("You are banned");
+                return;
+            }
+        }
+        let world = self.worlds.get(world.as_deref()).sender();
+        let _ = world.send(WorldCommand::Join {
+            client_id: self.id,
+            identity,
+            outbox: self.outbox.clone(),
+        });
+        self.world = Some(world);
     }
 }
+
 struct ServerApp {
-    server_state: Arc<Mutex<ServerState>>,
+    worlds: Arc<Worlds>,
+    client_ids: IdGen,
 }
+
 impl geng::net::server::App for ServerApp {
     type Client = Client;
     type ServerMessage = ServerMessage;
     type ClientMessage = ClientMessage;
-    fn connect(&mut self, mut sender: Box<dyn geng::net::Sender<ServerMessage>>) -> Client {
-        let mut server_state = self.server_state.lock().unwrap();
-        let (welcome, events) = server_state.model.welcome();
-        server_state.add_events(events);
-        let player_id = welcome.player_id;
-        sender.send(ServerMessage::Welcome(welcome));
-        let next_event_index = server_state.next_event_index;
-        server_state
-            .clients_next_event
-            .insert(player_id, next_event_index);
-        sender.send(ServerMessage::Update(vec![]));
+    fn connect(&mut self, sender: Box<dyn geng::net::Sender<ServerMessage>>) -> Client {
         Client {
-            server_state: self.server_state.clone(),
-            player_id,
-            sender,
+            id: self.client_ids.gen(),
+            worlds: self.worlds.clone(),
+            world: None,
+            outbox: spawn_writer(sender),
+            rate_limiter: rate_limit::RateLimiter::new(),
+            kicked: false,
         }
     }
 }
 
 pub struct Server {
-    server_state: Arc<Mutex<ServerState>>,
+    worlds: Arc<Worlds>,
+    world_threads: Vec<std::thread::JoinHandle<()>>,
     server: geng::net::Server<ServerApp>,
 }
 
 impl Server {
-    pub fn new<T: std::net::ToSocketAddrs + Debug + Copy>(addr: T, model: Model) -> Self {
-        let server_state = Arc::new(Mutex::new(ServerState::new(model)));
+    /// Clients that don't ask for a specific world join the first one
+    pub fn new<T: std::net::ToSocketAddrs + Debug + Copy>(
+        addr: T,
+        worlds: Vec<WorldSettings>,
+    ) -> Self {
+        let banned: Arc<Mutex<HashSet<String>>> = default();
+        let mut states = HashMap::new();
+        let mut world_threads = Vec::new();
+        for settings in &worlds {
+            let (sender, receiver) = mpsc::channel();
+            let server_state = ServerState::new(settings.clone(), banned.clone());
+            world_threads.push(std::thread::spawn(move || server_state.run(receiver)));
+            states.insert(
+                settings.name.clone(),
+                WorldHandle {
+                    sender: Mutex::new(sender),
+                },
+            );
+        }
+        let worlds = Arc::new(Worlds {
+            default: worlds.first().expect("No worlds to host").name.clone(),
+            states,
+            banned,
+        });
         Self {
-            server_state: server_state.clone(),
+            worlds: worlds.clone(),
+            world_threads,
             server: geng::net::Server::new(
                 ServerApp {
-                    server_state: server_state.clone(),
+                    worlds,
+                    client_ids: IdGen::new(),
                 },
                 addr,
             ),
         }
     }
-    pub fn handle(&self) -> geng::net::ServerHandle {
-        self.server.handle()
+    /// Starts writing the default world and all following events to a file
+    pub fn record(&self, path: impl AsRef<std::path::Path>) -> bincode::Result<()> {
+        let path = path.as_ref().to_owned();
+        self.worlds.get(None).run(move |server_state| {
+            server_state.recorder = Some(Recorder::create(path, &server_state.model)?);
+            Ok(())
+        })
     }
-    pub fn run(self) {
-        let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
-        let server_thread = std::thread::spawn({
-            let server_state = self.server_state;
-            let running = running.clone();
-            let mut sleep_time = 0;
-            move || {
-                while running.load(std::sync::atomic::Ordering::Relaxed) {
-                    // TODO: smoother TPS
-                    std::thread::sleep(std::time::Duration::from_millis(sleep_time));
-                    let mut server_state = server_state.lock().unwrap();
-                    let events = server_state.model.tick();
-                    server_state.add_events(events);
-                    sleep_time = (1000.0 / server_state.model.ticks_per_second) as u64;
+    /// Reads admin commands from stdin, type help for the list
+    pub fn spawn_console(&self) {
+        let worlds = self.worlds.clone();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        error!("Failed to read the console: {}", e);
+                        break;
+                    }
+                };
+                let output = worlds.execute(&line);
+                if !output.is_empty() {
+                    println!("{}", output);
                 }
             }
         });
+    }
+    /// Writes the leaderboards of all worlds to a JSON file every few seconds
+    pub fn spawn_leaderboard_export(&self, path: impl AsRef<std::path::Path>) {
+        const INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+        let path = path.as_ref().to_owned();
+        let worlds = self.worlds.clone();
+        std::thread::spawn(move || loop {
+            let leaderboards = worlds.leaderboards();
+            // Write to a temporary file first, so readers never see half of it
+            let temp_path = path.with_extension("tmp");
+            let result = std::fs::write(
+                &temp_path,
+                serde_json::to_string_pretty(&leaderboards).unwrap(),
+            )
+            .and_then(|()| std::fs::rename(&temp_path, &path));
+            if let Err(e) = result {
+                error!("Failed to export the leaderboard to {:?}: {}", path, e);
+            }
+            std::thread::sleep(INTERVAL);
+        });
+    }
+    /// Serves world status as JSON on /status and as Prometheus metrics on /metrics,
+    /// and the leaderboards on /leaderboard
+    pub fn spawn_status(&self, addr: impl std::net::ToSocketAddrs) -> std::io::Result<()> {
+        let worlds = self.worlds.clone();
+        http::spawn(addr, move |path| worlds.serve(path))
+    }
+    pub fn handle(&self) -> geng::net::ServerHandle {
+        self.server.handle()
+    }
+    pub fn run(self) {
         self.server.run();
-        running.store(false, std::sync::atomic::Ordering::Relaxed);
-        server_thread.join().expect("Failed to join server thread");
+        for world in self.worlds.states.values() {
+            world.send(WorldCommand::Stop);
+        }
+        for thread in self.world_threads {
+            thread.join().expect("Failed to join world thread");
+        }
     }
 }
 
@@ -0,0 +1,69 @@
+use super::*;
+
+/// Free flying camera used when watching the world without a player.
+pub struct Spectator {
+    pub position: Vec2<f32>,
+    pub fov: f32,
+    pub following: Option<Id>,
+    pub overview: bool,
+}
+
+impl Spectator {
+    pub const SPEED: f32 = 1.0;
+    pub const MIN_FOV: f32 = 3.0;
+    pub const MAX_FOV: f32 = 100.0;
+    pub const OVERVIEW_FOV: f32 = 60.0;
+    pub const ZOOM_SPEED: f64 = 0.002;
+
+    pub fn new(position: Vec2<f32>) -> Self {
+        Self {
+            position,
+            fov: 10.0,
+            following: None,
+            overview: false,
+        }
+    }
+    pub fn fov(&self) -> f32 {
+        if self.overview {
+            Self::OVERVIEW_FOV
+        } else {
+            self.fov
+        }
+    }
+    pub fn update(&mut self, geng: &Geng, delta_time: f32) {
+        let window = geng.window();
+        let mut direction = vec2(0.0, 0.0);
+        if window.is_key_pressed(geng::Key::A) || window.is_key_pressed(geng::Key::Left) {
+            direction.x -= 1.0;
+        }
+        if window.is_key_pressed(geng::Key::D) || window.is_key_pressed(geng::Key::Right) {
+            direction.x += 1.0;
+        }
+        if window.is_key_pressed(geng::Key::W) || window.is_key_pressed(geng::Key::Up) {
+            direction.y += 1.0;
+        }
+        if window.is_key_pressed(geng::Key::S) || window.is_key_pressed(geng::Key::Down) {
+            direction.y -= 1.0;
+        }
+        if direction != vec2(0.0, 0.0) {
+            self.following = None;
+        }
+        self.position += direction * self.fov() * Self::SPEED * delta_time;
+    }
+    pub fn zoom(&mut self, delta: f64) {
+        self.overview = false;
+        self.fov = clamp(
+            self.fov * (-delta * Self::ZOOM_SPEED).exp() as f32,
+            Self::MIN_FOV..=Self::MAX_FOV,
+        );
+    }
+    /// Starts following the player after the current one, or stops after the last one.
+    pub fn follow_next(&mut self, players: impl IntoIterator<Item = Id>) {
+        let mut players: Vec<Id> = players.into_iter().collect();
+        players.sort_by_key(|id| id.raw());
+        self.following = match self.following {
+            Some(current) => players.into_iter().find(|id| id.raw() > current.raw()),
+            None => players.first().copied(),
+        };
+    }
+}